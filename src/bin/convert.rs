@@ -0,0 +1,42 @@
+use pinyin::{BinaryModel, JsonModel};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "convert")]
+/// Convert a model between gzipped JSON and the binary format,
+/// the input format is detected from its content
+struct Opt {
+    /// input model file
+    #[structopt(name = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// output model file, written in the binary format
+    /// if its name ends with ".bin", gzipped JSON otherwise
+    #[structopt(name = "output", parse(from_os_str))]
+    output: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+
+    let mut magic = [0u8; 4];
+    let is_binary =
+        File::open(&opt.input)?.read_exact(&mut magic).is_ok() && magic == pinyin::BINARY_MAGIC;
+    let json_model = if is_binary {
+        BinaryModel::load_from_path(&opt.input)?.to_json()?
+    } else {
+        JsonModel::load_from_reader(BufReader::new(File::open(&opt.input)?))?
+    };
+
+    let writer = BufWriter::new(File::create(&opt.output)?);
+    if opt.output.extension() == Some("bin".as_ref()) {
+        BinaryModel::write_json(&json_model, writer)?;
+    } else {
+        json_model.save_to_writer(writer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use pinyin::LanguageModel;
+use serde::Serialize;
+use std::error::Error;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "eval")]
+/// Measure how well a model converts a test set
+struct Opt {
+    /// pinyin of the test set, one sentence per line
+    #[structopt(name = "input", parse(from_os_str))]
+    input: PathBuf,
+
+    /// expected chinese of the test set, one sentence per line
+    #[structopt(name = "expected", parse(from_os_str))]
+    expected: PathBuf,
+
+    /// directory of model1.json.gz, model2.json.gz and model3.json.gz,
+    /// model{1,2,3}.bin are used instead where present,
+    /// defaults to the bundled models if built with them,
+    /// otherwise to the current directory
+    #[structopt(short = "m", long = "model-dir", parse(from_os_str))]
+    model_dir: Option<PathBuf>,
+
+    /// how to combine the orders, see pinyin --smoothing,
+    /// interpolation weights of the missing orders are dropped
+    /// when evaluating the lower orders
+    #[structopt(short = "s", long = "smoothing", default_value = "none")]
+    smoothing: pinyin::Smoothing,
+
+    /// what to do with tokens that are not pinyin:
+    /// "error", "pass", "skip" or "split"
+    #[structopt(short = "u", long = "unknown", default_value = "skip")]
+    unknown: pinyin::UnknownPolicy,
+
+    /// mapping file with toned syllables, see pinyin --tones,
+    /// defaults to the tones of the mapping file the models were trained with
+    #[structopt(long = "tones", parse(from_os_str))]
+    tones: Option<PathBuf>,
+
+    /// layout of the tones file, see train --mapping-format
+    #[structopt(long = "tones-format", default_value = "table")]
+    tones_format: pinyin::MappingFormat,
+
+    /// tolerate commonly confused initials and finals, see pinyin --fuzzy
+    #[structopt(long = "fuzzy")]
+    fuzzy: Option<pinyin::FuzzyPinyin>,
+
+    /// number of syllables with the most errors to list
+    #[structopt(short = "w", long = "worst", default_value = "10")]
+    worst: usize,
+
+    /// print the report as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct OrderReport {
+    order: usize,
+    char_accuracy: f64,
+    sentence_accuracy: f64,
+    chars: usize,
+    correct_chars: usize,
+    sentences: usize,
+    correct_sentences: usize,
+    failed_sentences: usize,
+}
+
+#[derive(Serialize)]
+struct SyllableReport {
+    syllable: String,
+    total: usize,
+    wrong: usize,
+    error_rate: f64,
+}
+
+#[derive(Serialize)]
+struct Report {
+    /// accuracy with the models up to every order
+    orders: Vec<OrderReport>,
+    /// syllables decoded worst with all orders
+    worst_syllables: Vec<SyllableReport>,
+}
+
+fn print_text(report: &Report) {
+    println!("order\tchars\tsentences");
+    for order in &report.orders {
+        println!(
+            "{}\t{:.2}%\t{:.2}%",
+            order.order,
+            order.char_accuracy * 100.0,
+            order.sentence_accuracy * 100.0
+        );
+    }
+    if let Some(order) = report.orders.last() {
+        println!(
+            "{} of {} chars and {} of {} sentences correct, {} failed",
+            order.correct_chars,
+            order.chars,
+            order.correct_sentences,
+            order.sentences,
+            order.failed_sentences
+        );
+    }
+    if !report.worst_syllables.is_empty() {
+        println!("worst syllables:");
+        for syllable in &report.worst_syllables {
+            println!(
+                "{}\t{} of {} wrong\t{:.2}%",
+                syllable.syllable,
+                syllable.wrong,
+                syllable.total,
+                syllable.error_rate * 100.0
+            );
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let input = pinyin::read_lines(&opt.input)?;
+    let expected = pinyin::read_lines(&opt.expected)?;
+    if input.len() != expected.len() {
+        return Err(format!(
+            "{} lines of pinyin but {} expected sentences",
+            input.len(),
+            expected.len()
+        )
+        .into());
+    }
+
+    let model_dir = opt.model_dir.as_deref();
+    let models = [
+        pinyin::load_model::<pinyin::Match1>(model_dir)?,
+        pinyin::load_model::<pinyin::Match2>(model_dir)?,
+        pinyin::load_model::<pinyin::Match3>(model_dir)?,
+    ];
+
+    if let pinyin::Smoothing::Interpolation(weights) = &opt.smoothing {
+        if weights.len() != models.len() {
+            return Err(format!(
+                "{} interpolation weights for {} models",
+                weights.len(),
+                models.len()
+            )
+            .into());
+        }
+    }
+
+    let tones = match &opt.tones {
+        Some(path) => {
+            let file = pinyin::MappingFile::load_from_path(
+                path,
+                opt.tones_format,
+                pinyin::Encoding::Auto,
+            )?;
+            for warning in &file.warnings {
+                eprintln!("{}: {}", path.display(), warning);
+            }
+            file.tones
+        }
+        None => models[models.len() - 1]
+            .metadata()
+            .map(|metadata| metadata.tones.clone())
+            .unwrap_or_default(),
+    };
+
+    let mut orders = Vec::new();
+    let mut evaluation = pinyin::Evaluation::default();
+    for order in 1..=models.len() {
+        let smoothing = match &opt.smoothing {
+            pinyin::Smoothing::Interpolation(weights) => {
+                pinyin::Smoothing::Interpolation(weights[..order].to_vec())
+            }
+            smoothing => smoothing.clone(),
+        };
+        let mut model = pinyin::Smoothed::new(
+            models[..order]
+                .iter()
+                .map(|model| Box::new(model.clone()) as Box<dyn LanguageModel>)
+                .collect(),
+            smoothing,
+        )
+        .map_err(|err| format!("order {}: {}", order, err))?;
+        model.set_tones(&tones);
+        model.fuzzy = opt.fuzzy.clone();
+        evaluation = pinyin::evaluate(&model, &input, &expected, opt.unknown);
+        orders.push(OrderReport {
+            order,
+            char_accuracy: evaluation.char_accuracy(),
+            sentence_accuracy: evaluation.sentence_accuracy(),
+            chars: evaluation.chars,
+            correct_chars: evaluation.correct_chars,
+            sentences: evaluation.sentences,
+            correct_sentences: evaluation.correct_sentences,
+            failed_sentences: evaluation.failed_sentences,
+        });
+    }
+    let report = Report {
+        orders,
+        worst_syllables: evaluation
+            .worst_syllables(opt.worst)
+            .into_iter()
+            .map(|(syllable, errors)| SyllableReport {
+                syllable: syllable.to_string(),
+                total: errors.total,
+                wrong: errors.wrong,
+                error_rate: errors.error_rate(),
+            })
+            .collect(),
+    };
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_text(&report);
+    }
+    Ok(())
+}
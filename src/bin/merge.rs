@@ -0,0 +1,186 @@
+use pinyin::LanguageModel;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "merge")]
+/// Interpolate models trained on different corpora
+struct Opt {
+    /// directories of model1, model2 and model3, or count files with
+    /// --counts, each optionally followed by ":weight", 1 by default,
+    /// models should be Kneser-Ney or pruned ones, whose contexts are normalized
+    #[structopt(name = "inputs")]
+    inputs: Vec<String>,
+
+    /// the inputs are count files, merged into a count file
+    /// to train a model from
+    #[structopt(long = "counts")]
+    counts: bool,
+
+    /// output directory, or output count file with --counts
+    #[structopt(short = "o", long = "output", parse(from_os_str), default_value = ".")]
+    output: PathBuf,
+
+    /// pinyin of the development set, one sentence per line,
+    /// the accuracy of the merged model on it is reported
+    #[structopt(long = "dev-input", parse(from_os_str))]
+    dev_input: Option<PathBuf>,
+
+    /// chinese of the development set, one sentence per line,
+    /// the weights are tuned to maximize its likelihood if given
+    #[structopt(long = "dev-output", parse(from_os_str))]
+    dev_output: Option<PathBuf>,
+
+    /// how to combine the orders of the merged model when decoding
+    /// the development set, see pinyin --smoothing,
+    /// the weights are always tuned with katz smoothing like the merged model
+    #[structopt(short = "s", long = "smoothing", default_value = "katz")]
+    smoothing: pinyin::Smoothing,
+}
+
+/// Split "path:weight" into its parts
+fn parse_input(input: &str) -> Result<(PathBuf, f64), Box<dyn Error>> {
+    if let Some((path, weight)) = input.rsplit_once(':') {
+        if let Ok(weight) = weight.parse::<f64>() {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("invalid weight in {:?}", input).into());
+            }
+            return Ok((PathBuf::from(path), weight));
+        }
+    }
+    Ok((PathBuf::from(input), 1.0))
+}
+
+struct Models {
+    model1: Rc<pinyin::Model<pinyin::Match1>>,
+    model2: Rc<pinyin::Model<pinyin::Match2>>,
+    model3: Rc<pinyin::Model<pinyin::Match3>>,
+}
+
+impl Models {
+    fn load(dir: &Path) -> Result<Self, pinyin::Error> {
+        Ok(Models {
+            model1: Rc::new(pinyin::Model::load_from_dir(dir)?),
+            model2: Rc::new(pinyin::Model::load_from_dir(dir)?),
+            model3: Rc::new(pinyin::Model::load_from_dir(dir)?),
+        })
+    }
+
+    fn smoothed(&self, smoothing: &pinyin::Smoothing) -> Result<pinyin::Smoothed, pinyin::Error> {
+        pinyin::Smoothed::new(
+            vec![
+                Box::new(self.model1.clone()),
+                Box::new(self.model2.clone()),
+                Box::new(self.model3.clone()),
+            ],
+            smoothing.clone(),
+        )
+    }
+}
+
+fn merge_counts(opt: &Opt, inputs: &[(PathBuf, f64)]) -> Result<(), Box<dyn Error>> {
+    let mut counts = Vec::new();
+    for (path, _) in inputs {
+        counts.push(pinyin::JsonCounts::load_from_path(path)?);
+    }
+    let weights: Vec<f64> = inputs.iter().map(|(_, weight)| *weight).collect();
+    let output = if opt.output.is_dir() {
+        opt.output.join(pinyin::JsonCounts::file_name())
+    } else {
+        opt.output.clone()
+    };
+    let (merged, dropped) = pinyin::JsonCounts::merge(&counts, &weights)?;
+    if dropped > 0 {
+        eprintln!(
+            "dropped {} n-grams whose weighted count rounds to 0",
+            dropped
+        );
+    }
+    merged.save_to(output)?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    let inputs = opt
+        .inputs
+        .iter()
+        .map(|input| parse_input(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    if inputs.len() < 2 {
+        return Err("at least two inputs are needed".into());
+    }
+    if opt.counts {
+        return merge_counts(&opt, &inputs);
+    }
+
+    let mut models = Vec::new();
+    for (dir, _) in &inputs {
+        models.push(Models::load(dir)?);
+    }
+    let mut weights: Vec<f64> = inputs.iter().map(|(_, weight)| *weight).collect();
+    let dev_output = match &opt.dev_output {
+        Some(path) => Some(pinyin::read_lines(path)?),
+        None => None,
+    };
+    if let Some(dev_output) = &dev_output {
+        let smoothed: Vec<pinyin::Smoothed> = models
+            .iter()
+            .map(|models| models.smoothed(&pinyin::Smoothing::Katz))
+            .collect::<Result<_, _>>()?;
+        let smoothed: Vec<&dyn LanguageModel> = smoothed
+            .iter()
+            .map(|model| model as &dyn LanguageModel)
+            .collect();
+        let sentences: Vec<&str> = dev_output.iter().map(String::as_str).collect();
+        let (tuned, log_prob) = pinyin::tune_weights(&smoothed, &sentences);
+        println!("tuned weights: {:?}", tuned);
+        println!("dev log probability per char: {}", log_prob);
+        weights = tuned;
+    }
+
+    let inputs: Vec<_> = models
+        .iter()
+        .map(|models| (&*models.model1, &*models.model2, &*models.model3))
+        .collect();
+    let (model1, model2, model3) = pinyin::interpolate_models(&inputs, &weights)?;
+    let merged = Models {
+        model1: Rc::new(model1),
+        model2: Rc::new(model2),
+        model3: Rc::new(model3),
+    };
+
+    if let (Some(dev_input), Some(dev_output)) = (&opt.dev_input, &dev_output) {
+        let dev_input = pinyin::read_lines(dev_input)?;
+        let smoothed = merged.smoothed(&opt.smoothing)?;
+        println!(
+            "dev char accuracy: {:.2}%",
+            pinyin::evaluate(
+                &smoothed,
+                &dev_input,
+                dev_output,
+                pinyin::UnknownPolicy::Skip
+            )
+            .char_accuracy()
+                * 100.0
+        );
+    }
+
+    fs::create_dir_all(&opt.output)?;
+    merged.model1.save_to(
+        opt.output
+            .join(pinyin::Model::<pinyin::Match1>::file_name()),
+    )?;
+    merged.model2.save_to(
+        opt.output
+            .join(pinyin::Model::<pinyin::Match2>::file_name()),
+    )?;
+    merged.model3.save_to(
+        opt.output
+            .join(pinyin::Model::<pinyin::Match3>::file_name()),
+    )?;
+    Ok(())
+}
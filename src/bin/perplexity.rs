@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "perplexity")]
+/// Score held-out chinese text under a model
+struct Opt {
+    /// held-out text files, gzipped ones are decompressed
+    #[structopt(name = "files", parse(from_os_str))]
+    files: Vec<PathBuf>,
+
+    /// directory of model1.json.gz, model2.json.gz and model3.json.gz,
+    /// model{1,2,3}.bin are used instead where present
+    #[structopt(
+        short = "m",
+        long = "model-dir",
+        parse(from_os_str),
+        default_value = "."
+    )]
+    model_dir: PathBuf,
+
+    /// how to combine the orders, see pinyin --smoothing,
+    /// only katz and interpolation give normalized probabilities
+    #[structopt(short = "s", long = "smoothing", default_value = "katz")]
+    smoothing: pinyin::Smoothing,
+
+    /// score with the model of this order alone instead, only chars
+    /// with enough history are scored
+    #[structopt(long = "order")]
+    order: Option<usize>,
+
+    /// layout of the files, see train --format
+    #[structopt(short = "f", long = "format", default_value = "plain")]
+    format: pinyin::CorpusFormat,
+
+    /// encoding of the files, see train --encoding
+    #[structopt(short = "e", long = "encoding", default_value = "auto")]
+    encoding: pinyin::Encoding,
+
+    /// print the report as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+/// Score every text of `path` with `score`
+fn score_file<F>(opt: &Opt, path: &Path, score: F) -> Result<pinyin::Perplexity, Box<dyn Error>>
+where
+    F: Fn(&str) -> pinyin::Perplexity,
+{
+    let mut corpus = pinyin::open_corpus(File::open(path)?, &opt.format, opt.encoding)?;
+    let mut res = pinyin::Perplexity::default();
+    while let Some(text) = corpus.next_text()? {
+        res.merge(&score(&text));
+    }
+    Ok(res)
+}
+
+#[derive(Serialize)]
+struct Row {
+    file: String,
+    chars: usize,
+    oov: usize,
+    unseen: usize,
+    log_prob_per_char: f64,
+    cross_entropy: f64,
+    perplexity: f64,
+}
+
+impl Row {
+    fn new(file: String, perplexity: &pinyin::Perplexity) -> Self {
+        Row {
+            file,
+            chars: perplexity.chars,
+            oov: perplexity.oov,
+            unseen: perplexity.unseen,
+            log_prob_per_char: perplexity.log_prob_per_char(),
+            cross_entropy: perplexity.cross_entropy(),
+            perplexity: perplexity.perplexity(),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opt = Opt::from_args();
+    if opt.files.is_empty() {
+        return Err("no files to score".into());
+    }
+
+    let mut results = Vec::new();
+    match opt.order {
+        Some(1) => {
+            let model = pinyin::load_model::<pinyin::Match1>(Some(&opt.model_dir))?;
+            for file in &opt.files {
+                results.push(score_file(&opt, file, |text| {
+                    pinyin::ngram_perplexity(&model, text)
+                })?);
+            }
+        }
+        Some(2) => {
+            let model = pinyin::load_model::<pinyin::Match2>(Some(&opt.model_dir))?;
+            for file in &opt.files {
+                results.push(score_file(&opt, file, |text| {
+                    pinyin::ngram_perplexity(&model, text)
+                })?);
+            }
+        }
+        Some(3) => {
+            let model = pinyin::load_model::<pinyin::Match3>(Some(&opt.model_dir))?;
+            for file in &opt.files {
+                results.push(score_file(&opt, file, |text| {
+                    pinyin::ngram_perplexity(&model, text)
+                })?);
+            }
+        }
+        Some(order) => return Err(format!("no model of order {}", order).into()),
+        None => {
+            let unnormalized = match opt.smoothing {
+                pinyin::Smoothing::None => Some("none"),
+                pinyin::Smoothing::Backoff(_) => Some("backoff"),
+                _ => None,
+            };
+            if let Some(smoothing) = unnormalized {
+                eprintln!(
+                    "warning: {} smoothing does not give normalized probabilities, \
+                     the perplexity is not comparable to other models",
+                    smoothing
+                );
+            }
+            let model = pinyin::Smoothed::new(
+                vec![
+                    Box::new(pinyin::load_model::<pinyin::Match1>(Some(&opt.model_dir))?),
+                    Box::new(pinyin::load_model::<pinyin::Match2>(Some(&opt.model_dir))?),
+                    Box::new(pinyin::load_model::<pinyin::Match3>(Some(&opt.model_dir))?),
+                ],
+                opt.smoothing.clone(),
+            )?;
+            for file in &opt.files {
+                results.push(score_file(&opt, file, |text| {
+                    pinyin::perplexity(&model, text)
+                })?);
+            }
+        }
+    }
+
+    let mut total = pinyin::Perplexity::default();
+    let mut rows = Vec::new();
+    for (file, result) in opt.files.iter().zip(&results) {
+        total.merge(result);
+        rows.push(Row::new(file.display().to_string(), result));
+    }
+    if rows.len() > 1 {
+        rows.push(Row::new("total".to_string(), &total));
+    }
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else {
+        println!("file\tchars\toov\tunseen\tlog prob/char\tcross-entropy\tperplexity");
+        for row in &rows {
+            println!(
+                "{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.2}",
+                row.file,
+                row.chars,
+                row.oov,
+                row.unseen,
+                row.log_prob_per_char,
+                row.cross_entropy,
+                row.perplexity
+            );
+        }
+    }
+    Ok(())
+}
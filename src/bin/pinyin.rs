@@ -1,13 +1,15 @@
-use pinyin;
+use std::error::Error;
 use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, Result, Write};
-use std::path::PathBuf;
+use std::io::{stdin, stdout, BufRead, BufReader, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "pinyin")]
 struct Opt {
     /// input file name, one sentence per line,
+    /// syllables may be separated by spaces or apostrophes,
     /// use "-" for stdin
     #[structopt(name = "input", parse(from_os_str))]
     input: PathBuf,
@@ -16,52 +18,146 @@ struct Opt {
     /// use "-" for stdout
     #[structopt(name = "output", parse(from_os_str))]
     output: PathBuf,
+
+    /// print the n best candidates of every sentence
+    /// along with their log probabilities
+    #[structopt(short = "n", long = "nbest")]
+    nbest: Option<NonZeroUsize>,
+
+    /// how to combine the unigram, bigram and trigram models:
+    /// "none", "backoff[:alpha]", "katz" or "interpolation[:w1,w2,w3]"
+    #[structopt(short = "s", long = "smoothing", default_value = "none")]
+    smoothing: pinyin::Smoothing,
+
+    /// what to do with tokens that are not pinyin:
+    /// "error", "pass", "skip" or "split"
+    #[structopt(short = "u", long = "unknown", default_value = "error")]
+    unknown: pinyin::UnknownPolicy,
+
+    /// directory of model1.json.gz, model2.json.gz and model3.json.gz,
+    /// model{1,2,3}.bin are used instead where present,
+    /// defaults to the bundled models if built with them,
+    /// otherwise to the current directory
+    #[structopt(short = "m", long = "model-dir", parse(from_os_str))]
+    model_dir: Option<PathBuf>,
+
+    /// mapping file with toned syllables, tones given in the input restrict
+    /// the candidates to the chars listed for them, defaults to the tones
+    /// of the mapping file the models were trained with,
+    /// without any tones are accepted but ignored
+    #[structopt(long = "tones", parse(from_os_str))]
+    tones: Option<PathBuf>,
+
+    /// layout of the tones file, see train --mapping-format
+    #[structopt(long = "tones-format", default_value = "table")]
+    tones_format: pinyin::MappingFormat,
+
+    /// tolerate commonly confused initials and finals, "all[:weight]" or
+    /// pairs like "z/zh,n/l:0.3" among z/zh, c/ch, s/sh, n/l, f/h, an/ang,
+    /// en/eng and in/ing, the weight defaults to 0.1
+    #[structopt(long = "fuzzy")]
+    fuzzy: Option<pinyin::FuzzyPinyin>,
+
+    /// print the syllable graph, every decoding step
+    /// and the chosen path to stderr
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
 }
 
-fn main() -> Result<()> {
+/// Print the decoder internals to stderr
+struct Verbose;
+
+impl pinyin::Observer for Verbose {
+    fn graph(&mut self, graph: &pinyin::SyllableGraph) {
+        for edge in &graph.edges {
+            eprintln!("syllable {}..{}: {}", edge.start, edge.end, edge.syllable);
+        }
+    }
+
+    fn step(&mut self, end: usize, nodes: &[pinyin::Node]) {
+        eprintln!("step {}: {:?}", end, nodes);
+    }
+
+    fn path(&mut self, nodes: &[pinyin::Node]) {
+        let path: String = nodes.iter().map(|node| node.ch).collect();
+        let score = nodes.last().map_or(0.0, |node| node.score);
+        eprintln!("path: {} {}", path, score);
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     let opt = Opt::from_args();
     let stdin = stdin();
     let stdout = stdout();
-    let mut input_file: Box<dyn BufRead> = if opt.input != PathBuf::from("-") {
+    let mut input_file: Box<dyn BufRead> = if opt.input != Path::new("-") {
         Box::new(BufReader::new(File::open(&opt.input)?))
     } else {
         Box::new(stdin.lock())
     };
-    let mut output_file: Box<dyn Write> = if opt.output != PathBuf::from("-") {
+    let mut output_file: Box<dyn Write> = if opt.output != Path::new("-") {
         Box::new(File::create(&opt.output)?)
     } else {
         Box::new(stdout.lock())
     };
 
-    let model1 = pinyin::Model::<pinyin::Match1>::load();
-    let model2 = pinyin::Model::<pinyin::Match2>::load();
-    let model3 = pinyin::Model::<pinyin::Match3>::load();
+    let model_dir = opt.model_dir.as_deref();
+    let mut model = pinyin::Smoothed::new(
+        vec![
+            Box::new(pinyin::load_model::<pinyin::Match1>(model_dir)?),
+            Box::new(pinyin::load_model::<pinyin::Match2>(model_dir)?),
+            Box::new(pinyin::load_model::<pinyin::Match3>(model_dir)?),
+        ],
+        opt.smoothing.clone(),
+    )?;
+    let mut tones = model.stored_tones();
+    if let Some(path) = &opt.tones {
+        let file =
+            pinyin::MappingFile::load_from_path(path, opt.tones_format, pinyin::Encoding::Auto)?;
+        for warning in &file.warnings {
+            eprintln!("{}: {}", path.display(), warning);
+        }
+        tones = file.tones;
+    }
+    model.set_tones(&tones);
+    model.fuzzy = opt.fuzzy.clone();
+    let segmenter = pinyin::Segmenter::new(model.spellings());
 
+    let mut line_number = 0;
     loop {
         let mut line = String::new();
-        if input_file.read_line(&mut line).is_err() {
-            break;
+        match input_file.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            _ => {}
         }
-        let words: Vec<&str> = line.trim().split(|c| c == ' ').collect();
-        if words.is_empty() {
+        line_number += 1;
+        if line.trim().is_empty() {
             continue;
         }
-        if words.len() == 1 {
-            let (result1, _) = model1.convert(&vec![words[0]], None);
-            output_file.write(&result1.as_bytes())?;
-            output_file.flush()?;
-        } else if words.len() == 2 {
-            let (_, prob1) = model1.convert(&vec![words[0]], None);
-            let (result2, _) = model2.convert(&words, Some(prob1));
-            output_file.write(&result2.as_bytes())?;
-            output_file.flush()?;
-        } else {
-            let (_, prob1) = model1.convert(&vec![words[0]], None);
-            let (_, prob2) = model2.convert(&vec![words[0], words[1]], Some(prob1));
-            let (result3, _) = model3.convert(&words, Some(prob2));
-            output_file.write(&result3.as_bytes())?;
-            output_file.flush()?;
+        let pieces = segmenter.pieces(&line);
+        let n = opt.nbest.map_or(1, NonZeroUsize::get);
+        let observer: &mut dyn pinyin::Observer = if opt.verbose { &mut Verbose } else { &mut () };
+        let results = match model.convert_pieces_observed(&pieces, n, opt.unknown, observer) {
+            Ok(results) => results,
+            Err(err) => {
+                eprintln!("line {}: {}", line_number, err);
+                Vec::new()
+            }
+        };
+        match opt.nbest {
+            Some(_) => {
+                for (result, score) in results {
+                    writeln!(output_file, "{}\t{}", result, score)?;
+                }
+                writeln!(output_file)?;
+            }
+            None => {
+                if let Some((result, _score)) = results.first() {
+                    output_file.write_all(result.as_bytes())?;
+                }
+                output_file.write_all(b"\n")?;
+            }
         }
+        output_file.flush()?;
     }
 
     Ok(())
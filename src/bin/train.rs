@@ -1,22 +1,22 @@
 extern crate structopt;
 
-use encoding_rs::GBK;
-use pinyin;
-use serde::Deserialize;
-use std::collections::{BTreeMap, BTreeSet};
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use pinyin::{self, CorpusReader, Match};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-#[derive(Debug, Deserialize)]
-pub struct News {
-    html: String,
-    time: String,
-    title: String,
-    url: String,
-}
-
 #[derive(StructOpt, Debug)]
 #[structopt(name = "train")]
 struct Opt {
@@ -24,100 +24,618 @@ struct Opt {
     #[structopt(name = "pinyin", parse(from_os_str))]
     pinyin: PathBuf,
 
-    /// data files
+    /// layout of the mapping file: "table" for a syllable and its chars
+    /// per line, "unihan" for Unihan readings or "cedict" for CC-CEDICT
+    #[structopt(long = "mapping-format", default_value = "table")]
+    mapping_format: pinyin::MappingFormat,
+
+    /// encoding of the mapping file: "utf-8", "gbk",
+    /// or "auto" to detect it from the whole file
+    #[structopt(long = "mapping-encoding", default_value = "auto")]
+    mapping_encoding: pinyin::Encoding,
+
+    /// data files, gzipped ones are decompressed
     #[structopt(name = "files", parse(from_os_str))]
     files: Vec<PathBuf>,
+
+    /// layout of the data files: "plain" for one text per line,
+    /// "jsonl[:field.path]" for JSON lines with the text at the path,
+    /// "text" by default, or "news" for Sina news articles
+    #[structopt(short = "f", long = "format", default_value = "news")]
+    format: pinyin::CorpusFormat,
+
+    /// encoding of the data files: "utf-8", "gbk",
+    /// or "auto" to detect it line by line
+    #[structopt(short = "e", long = "encoding", default_value = "auto")]
+    encoding: pinyin::Encoding,
+
+    /// also count the titles of news articles
+    #[structopt(long = "titles")]
+    titles: bool,
+
+    /// count texts with their HTML tags
+    #[structopt(long = "keep-tags")]
+    keep_tags: bool,
+
+    /// count texts with their HTML entities undecoded
+    #[structopt(long = "keep-entities")]
+    keep_entities: bool,
+
+    /// width to normalize punctuation to: "half", "full" or "keep"
+    #[structopt(long = "punctuation", default_value = "half")]
+    punctuation: pinyin::Punctuation,
+
+    /// estimate with interpolated modified Kneser-Ney smoothing
+    /// instead of maximum likelihood
+    #[structopt(long = "kneser-ney")]
+    kneser_ney: bool,
+
+    /// directory to write model1.json.gz, model2.json.gz
+    /// and model3.json.gz into, created if missing
+    #[structopt(
+        short = "o",
+        long = "output-dir",
+        parse(from_os_str),
+        default_value = "."
+    )]
+    output_dir: PathBuf,
+
+    /// number of counting threads, defaults to the number of CPUs
+    #[structopt(short = "j", long = "threads")]
+    threads: Option<usize>,
+
+    /// keep the counts and the texts queued for the counting threads
+    /// under about this many MiB while reading the corpus by spilling
+    /// the counts to disk, --kneser-ney still loads all counts at the end
+    /// and the estimated model is held in memory either way
+    #[structopt(short = "M", long = "memory-limit")]
+    memory_limit: Option<usize>,
+
+    /// directory for spilled counts, defaults to the system temp directory
+    #[structopt(long = "temp-dir", parse(from_os_str))]
+    temp_dir: Option<PathBuf>,
+
+    /// count file to add the counts of the data files to,
+    /// may be given several times to merge count files
+    #[structopt(short = "c", long = "counts", parse(from_os_str), number_of_values = 1)]
+    counts: Vec<PathBuf>,
+
+    /// write the raw n-gram counts to this file,
+    /// to add more data files later without counting again
+    #[structopt(long = "save-counts", parse(from_os_str))]
+    save_counts: Option<PathBuf>,
+
+    /// drop the n-grams of an order seen fewer times than a count,
+    /// given as "order:count" for order 2 or 3, may be given several times
+    #[structopt(long = "min-count", number_of_values = 1)]
+    min_count: Vec<MinCount>,
+
+    /// prune bigrams and trigrams by relative entropy until
+    /// at most this many n-grams of all orders are left
+    #[structopt(long = "prune-size")]
+    prune_size: Option<usize>,
 }
-fn main() {
-    let opt = Opt::from_args();
 
-    // insert pinyin mapping
-    let mut all_char: BTreeSet<char> = BTreeSet::new();
-    let mut pinyin_data = Vec::new();
-    File::open(opt.pinyin)
-        .expect("pinyin")
-        .read_to_end(&mut pinyin_data)
-        .expect("read pinyin");
-    let pinyin = GBK.decode(&pinyin_data).0;
-
-    let mut model1: pinyin::Model<pinyin::Match1> = pinyin::Model::empty();
-    let mut model2: pinyin::Model<pinyin::Match2> = pinyin::Model::empty();
-    let mut model3: pinyin::Model<pinyin::Match3> = pinyin::Model::empty();
-    for line in pinyin.split(|ch| ch == '\r' || ch == '\n') {
-        if line.is_empty() {
-            continue;
+/// Count cutoff of one order
+#[derive(Debug)]
+struct MinCount {
+    order: usize,
+    count: u32,
+}
+
+impl FromStr for MinCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (order, count) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected order:count, got {:?}", s))?;
+        let order = order
+            .parse()
+            .map_err(|_| format!("invalid order {:?}", order))?;
+        if order != 2 && order != 3 {
+            return Err(format!("can only cut off orders 2 and 3, not {}", order));
         }
-        let mut words = line.split(|ch| ch == ' ');
-        let pinyin = String::from(words.next().unwrap());
-        let chinese: Vec<char> = words.map(|s| s.chars().next().unwrap()).collect();
+        let count = count
+            .parse()
+            .map_err(|_| format!("invalid count {:?}", count))?;
+        Ok(MinCount { order, count })
+    }
+}
+
+/// Bytes of undecoded lines handed to a counting thread at once, a chunk
+/// ends with the first line reaching it
+const CHUNK_BYTES: usize = 256 << 10;
+
+/// Bytes of lines at most queued for or being counted by `threads` threads:
+/// the channel holds two chunks per thread, every thread counts one and
+/// the reader fills one
+fn queued_bytes(threads: usize) -> usize {
+    (threads * 3 + 1) * CHUNK_BYTES
+}
+
+/// Counts the bytes read from a file, for progress reports
+struct CountingReader {
+    file: File,
+    read: Rc<Cell<u64>>,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.file.read(buf)?;
+        self.read.set(self.read.get() + bytes as u64);
+        Ok(bytes)
+    }
+}
+
+/// Report to stderr how much of the corpus has been read
+struct Progress {
+    total: u64,
+    /// bytes of the files read completely
+    finished: u64,
+    /// bytes read from the current file
+    current: u64,
+    texts: u64,
+    last: Instant,
+}
 
-        for ch in chinese.iter() {
-            all_char.insert(*ch);
+impl Progress {
+    fn new(total: u64) -> Self {
+        Progress {
+            total,
+            finished: 0,
+            current: 0,
+            texts: 0,
+            last: Instant::now(),
         }
+    }
 
-        model1.mapping.insert(pinyin.clone(), chinese.clone());
-        model2.mapping.insert(pinyin.clone(), chinese.clone());
-        model3.mapping.insert(pinyin.clone(), chinese.clone());
+    /// Account for a chunk read from `file`, after reading `read` bytes of
+    /// it and counting `texts` texts of all files
+    fn update(&mut self, file: &Path, read: u64, texts: u64) {
+        self.current = read;
+        self.texts = texts;
+        if self.last.elapsed() >= Duration::from_millis(500) {
+            self.last = Instant::now();
+            self.print(file);
+        }
     }
 
-    // collect probabilities
-    let mut count1: BTreeMap<pinyin::Match1Prefix, u32> = BTreeMap::new();
-    let mut occur1: BTreeMap<pinyin::Match1, u32> = BTreeMap::new();
-    let mut count2: BTreeMap<pinyin::Match2Prefix, u32> = BTreeMap::new();
-    let mut occur2: BTreeMap<pinyin::Match2, u32> = BTreeMap::new();
-    let mut count3: BTreeMap<pinyin::Match3Prefix, u32> = BTreeMap::new();
-    let mut occur3: BTreeMap<pinyin::Match3, u32> = BTreeMap::new();
-    for file in opt.files {
-        println!("Processing file {:?}", file);
-        let mut data = Vec::new();
-        File::open(file)
-            .expect("open")
-            .read_to_end(&mut data)
-            .expect("read");
-        let content = GBK.decode(&data).0;
-        for line in content.split(|ch| ch == '\r' || ch == '\n') {
-            if line.is_empty() {
-                continue;
+    /// Print the final progress of `file` on its own line
+    fn finish(&mut self, file: &Path) {
+        self.print(file);
+        eprintln!();
+        self.finished += self.current;
+        self.current = 0;
+    }
+
+    fn print(&self, file: &Path) {
+        const MIB: f64 = (1 << 20) as f64;
+        let done = self.finished + self.current;
+        eprint!(
+            "\r{:5.1}% {:.1}/{:.1} MiB, {} texts counted, {}",
+            done as f64 * 100.0 / self.total.max(1) as f64,
+            done as f64 / MIB,
+            self.total as f64 / MIB,
+            self.texts,
+            file.display()
+        );
+    }
+}
+
+/// Read the lines of `files` and send them in chunks to `sender`, with the
+/// index of their file, reporting the `texts` counted so far
+///
+/// Gzipped files are decompressed here, decoding and parsing the lines is
+/// left to the counting threads.
+fn read_corpus(
+    files: &[PathBuf],
+    texts: &AtomicU64,
+    sender: SyncSender<(usize, pinyin::CorpusChunk)>,
+) -> Result<(), String> {
+    let total = files
+        .iter()
+        .map(|file| fs::metadata(file).map_or(0, |metadata| metadata.len()))
+        .sum();
+    let mut progress = Progress::new(total);
+    for (index, file) in files.iter().enumerate() {
+        let failed = |err: &dyn std::fmt::Display| format!("{}: {}", file.display(), err);
+        let read = Rc::new(Cell::new(0));
+        let reader = CountingReader {
+            file: File::open(file).map_err(|err| failed(&err))?,
+            read: read.clone(),
+        };
+        let mut chunks = pinyin::CorpusChunks::open(reader).map_err(|err| failed(&err))?;
+        while let Some(chunk) = chunks.next_chunk(CHUNK_BYTES).map_err(|err| failed(&err))? {
+            // the receiver is dropped when a counting thread fails
+            if sender.send((index, chunk)).is_err() {
+                return Ok(());
             }
-            let news: News = serde_json::from_str(line).expect("parsing");
+            progress.update(file, read.get(), texts.load(Ordering::Relaxed));
+        }
+        progress.finish(file);
+    }
+    Ok(())
+}
 
-            let match1_iter = pinyin::Match1::iter(&news.html, &all_char);
-            for match1 in match1_iter {
-                *count1.entry(match1.get_prefix()).or_insert(0) += 1;
-                *occur1.entry(match1).or_insert(0) += 1;
+/// Count the n-grams in `files` with `threads` threads, each counting
+/// into its own counter made by `new` with `add`
+fn count<C, N, A>(opt: &Opt, threads: usize, new: N, add: A) -> Result<Vec<C>, String>
+where
+    C: Send,
+    N: Fn(usize) -> C + Sync,
+    A: Fn(&mut C, &str) -> Result<(), pinyin::Error> + Sync,
+{
+    let (sender, receiver) = mpsc::sync_channel::<(usize, pinyin::CorpusChunk)>(threads * 2);
+    let receiver = &Mutex::new(Some(receiver));
+    let cleaner = &pinyin::TextCleaner {
+        strip_tags: !opt.keep_tags,
+        decode_entities: !opt.keep_entities,
+        punctuation: opt.punctuation,
+    };
+    let texts = &AtomicU64::new(0);
+    let (new, add) = (&new, &add);
+    let count_chunk = move |counter: &mut C, chunk: pinyin::CorpusChunk| {
+        let mut chunk_texts = chunk.texts(&opt.format, opt.encoding);
+        while let Some(text) = chunk_texts.next_text()? {
+            add(counter, &cleaner.clean(&text))?;
+            texts.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok::<_, pinyin::Error>(())
+    };
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|index| {
+                scope.spawn(move || {
+                    let mut counter = new(index);
+                    loop {
+                        let chunk = match receiver.lock().unwrap().as_ref() {
+                            Some(receiver) => receiver.recv(),
+                            None => break,
+                        };
+                        let (file, chunk) = match chunk {
+                            Ok(chunk) => chunk,
+                            Err(_) => break,
+                        };
+                        if let Err(err) = count_chunk(&mut counter, chunk) {
+                            // stop the reader instead of leaving it blocked on a full channel
+                            receiver.lock().unwrap().take();
+                            return Err(format!("{}: {}", opt.files[file].display(), err));
+                        }
+                    }
+                    Ok(counter)
+                })
+            })
+            .collect();
+
+        let read = read_corpus(&opt.files, texts, sender);
+        let counters: Result<Vec<_>, _> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("counting thread"))
+            .collect();
+        let counters = counters?;
+        read?;
+        eprintln!("{} texts counted", texts.load(Ordering::Relaxed));
+        Ok(counters)
+    })
+}
+
+/// Counts of every order, in memory or in sorted run files
+enum Counted {
+    Memory(pinyin::Counts),
+    Runs(Vec<Vec<PathBuf>>),
+}
+
+impl Counted {
+    /// Counts of the n-grams of `order` sorted by key
+    fn sorted(&self, order: usize) -> Box<dyn Iterator<Item = (u64, u32)>> {
+        match self {
+            Counted::Memory(counts) => Box::new(counts.sorted(order).into_iter()),
+            Counted::Runs(runs) => Box::new(
+                pinyin::MergedRuns::open(&runs[order - 1])
+                    .expect("open runs")
+                    .map(|entry| entry.expect("read runs")),
+            ),
+        }
+    }
+
+    /// N-grams of order `T::order()` seen fewer than `min_count` times,
+    /// which are all in the estimated model already
+    fn below<T: Match>(&self, min_count: u32, vocab: &pinyin::Vocab) -> Vec<T> {
+        self.sorted(T::order())
+            .filter(|(_, count)| *count < min_count)
+            .map(|(key, _)| T::from_chars(&vocab.unpack(key, T::order())))
+            .collect()
+    }
+
+    /// Counts of the n-grams of `order` by their chars, all in memory
+    fn to_chars(&self, order: usize, vocab: &pinyin::Vocab) -> BTreeMap<Vec<char>, u32> {
+        self.sorted(order)
+            .map(|(key, count)| (vocab.unpack(key, order), count))
+            .collect()
+    }
+}
+
+/// Maximum likelihood estimation of `P(last char | prefix)` from counts
+/// sorted by key, in which n-grams with the same prefix are adjacent
+fn normalize<T: Match, I: Iterator<Item = (u64, u32)>>(
+    entries: I,
+    vocab: &pinyin::Vocab,
+    model: &mut pinyin::Model<T>,
+) {
+    let mut group: Vec<(u64, u32)> = Vec::new();
+    for (key, count) in entries {
+        if let Some((last, _)) = group.last() {
+            if pinyin::Vocab::prefix(*last) != pinyin::Vocab::prefix(key) {
+                insert_group(&mut group, vocab, model);
             }
+        }
+        group.push((key, count));
+    }
+    insert_group(&mut group, vocab, model);
+}
 
-            let match2_iter = pinyin::Match2::iter(&news.html, &all_char);
-            for match2 in match2_iter {
-                *count2.entry(match2.get_prefix()).or_insert(0) += 1;
-                *occur2.entry(match2).or_insert(0) += 1;
+/// Insert the n-grams of `group`, which share a prefix, and clear it
+fn insert_group<T: Match>(
+    group: &mut Vec<(u64, u32)>,
+    vocab: &pinyin::Vocab,
+    model: &mut pinyin::Model<T>,
+) {
+    let total: u64 = group.iter().map(|(_, count)| *count as u64).sum();
+    for (key, count) in group.drain(..) {
+        let prob = (count as f64) / (total as f64);
+        model
+            .insert_prob(&T::from_chars(&vocab.unpack(key, T::order())), prob)
+            .expect("insert n-gram");
+    }
+}
+
+/// Directory of the run files of spilled counts, removed when dropped
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            // panicking while unwinding would abort
+            if let Err(err) = fs::remove_dir_all(&self.0) {
+                eprintln!("cannot remove {}: {}", self.0.display(), err);
             }
+        }
+    }
+}
+
+/// Report a corpus that cannot be counted and exit
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let mut opt = Opt::from_args();
+    if opt.titles {
+        match &mut opt.format {
+            pinyin::CorpusFormat::News { titles } => *titles = true,
+            _ => panic!("--titles only applies to the news format"),
+        }
+    }
+
+    // insert pinyin mapping
+    let mapping_file =
+        pinyin::MappingFile::load_from_path(&opt.pinyin, opt.mapping_format, opt.mapping_encoding)
+            .unwrap_or_else(|err| panic!("{}: {}", opt.pinyin.display(), err));
+    for warning in &mapping_file.warnings {
+        eprintln!("{}: {}", opt.pinyin.display(), warning);
+    }
+    let all_char = mapping_file.chars();
+    let mapping = mapping_file.mapping;
+    let tones = mapping_file.tones;
+    let vocab = pinyin::Vocab::from_mapping(&mapping)
+        .unwrap_or_else(|err| panic!("{}: {}", opt.pinyin.display(), err));
 
-            let match3_iter = pinyin::Match3::iter(&news.html, &all_char);
-            for match3 in match3_iter {
-                *count3.entry(match3.get_prefix()).or_insert(0) += 1;
-                *occur3.entry(match3).or_insert(0) += 1;
+    // previous counts
+    let mut corpus = Vec::new();
+    let mut loaded = pinyin::Counts::new(3).expect("counts");
+    for path in &opt.counts {
+        let json_counts = pinyin::JsonCounts::load_from_path(path).expect("load counts");
+        if json_counts.order() != 3 {
+            panic!(
+                "{:?} holds counts of order {}, expected 3",
+                path,
+                json_counts.order()
+            );
+        }
+        let dropped = loaded.add_json(&json_counts, &vocab);
+        if dropped > 0 {
+            eprintln!(
+                "{:?}: dropped {} n-grams with chars not in the mapping",
+                path, dropped
+            );
+        }
+        corpus.extend(json_counts.corpus);
+    }
+    corpus.extend(opt.files.iter().map(|file| file.display().to_string()));
+
+    // collect probabilities
+    let threads = opt
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()));
+    let threads = threads.max(1);
+    let temp_dir = TempDir(
+        opt.temp_dir
+            .clone()
+            .unwrap_or_else(env::temp_dir)
+            .join(format!("pinyin-train-{}", process::id())),
+    );
+    let counted = match opt.memory_limit {
+        Some(limit) => {
+            fs::create_dir_all(&temp_dir.0).expect("create temp dir");
+            let queued = queued_bytes(threads);
+            if limit << 20 <= queued {
+                eprintln!(
+                    "memory limit below the {} MiB of texts queued for {} threads",
+                    queued >> 20,
+                    threads
+                );
+            }
+            let limit = (limit << 20).saturating_sub(queued) / threads;
+            let counters = count(
+                &opt,
+                threads,
+                |index| {
+                    pinyin::SpillingCounts::new(3, &temp_dir.0, &format!("thread{}", index), limit)
+                        .expect("counts")
+                },
+                |counter, text| counter.add_text(text, &vocab),
+            );
+            let counters = match counters {
+                Ok(counters) => counters,
+                Err(err) => {
+                    // exiting skips destructors
+                    drop(temp_dir);
+                    exit(&err)
+                }
+            };
+            let mut previous =
+                pinyin::SpillingCounts::new(3, &temp_dir.0, "previous", limit).expect("counts");
+            previous.merge(loaded).expect("spill counts");
+            let mut runs = vec![Vec::new(); 3];
+            for counter in counters.into_iter().chain(Some(previous)) {
+                for (runs, counter_runs) in
+                    runs.iter_mut().zip(counter.finish().expect("spill counts"))
+                {
+                    runs.extend(counter_runs);
+                }
+            }
+            Counted::Runs(runs)
+        }
+        None => {
+            let counters = count(
+                &opt,
+                threads,
+                |_| pinyin::Counts::new(3).expect("counts"),
+                |counts, text| {
+                    counts.add_text(text, &vocab);
+                    Ok(())
+                },
+            )
+            .unwrap_or_else(|err| exit(&err));
+            let mut counts = loaded;
+            for counter in counters {
+                counts.merge(counter);
             }
+            Counted::Memory(counts)
         }
+    };
+
+    if let Some(path) = &opt.save_counts {
+        let vocab = &vocab;
+        let tables = (1..=3)
+            .map(|order| {
+                counted.sorted(order).map(move |(key, count)| {
+                    (vocab.unpack(key, order).into_iter().collect(), count)
+                })
+            })
+            .collect();
+        let writer = BufWriter::new(File::create(path).expect("create counts"));
+        pinyin::JsonCounts::save_streamed(&corpus, &mapping, tables, writer).expect("save counts");
     }
 
-    for (match1, o) in &occur1 {
-        let prob = (*o as f64) / (*count1.get(&match1.get_prefix()).expect("found") as f64);
-        model1.prob.insert(*match1, prob);
+    let mut model1: pinyin::Model<pinyin::Match1> =
+        pinyin::Model::from_mapping(mapping.clone()).expect("model1");
+    let mut model2: pinyin::Model<pinyin::Match2> =
+        pinyin::Model::from_mapping(mapping.clone()).expect("model2");
+    let mut model3: pinyin::Model<pinyin::Match3> =
+        pinyin::Model::from_mapping(mapping).expect("model3");
+    let mut metadata = pinyin::Metadata::new(0);
+    metadata.smoothing = if opt.kneser_ney { "kneser-ney" } else { "mle" }.to_string();
+    metadata.corpus = corpus.join(", ");
+    metadata.tones = tones;
+    model1.metadata = metadata.clone();
+    model2.metadata = metadata.clone();
+    model3.metadata = metadata;
+
+    if opt.kneser_ney {
+        let occur = [
+            counted.to_chars(1, &vocab),
+            counted.to_chars(2, &vocab),
+            counted.to_chars(3, &vocab),
+        ];
+        let estimates = pinyin::kneser_ney(&occur, &all_char);
+        model1
+            .insert_estimate(&estimates[0])
+            .expect("insert estimate");
+        model2
+            .insert_estimate(&estimates[1])
+            .expect("insert estimate");
+        model3
+            .insert_estimate(&estimates[2])
+            .expect("insert estimate");
+    } else {
+        normalize(counted.sorted(1), &vocab, &mut model1);
+        normalize(counted.sorted(2), &vocab, &mut model2);
+        normalize(counted.sorted(3), &vocab, &mut model3);
     }
 
-    for (match2, o) in &occur2 {
-        let prob = (*o as f64) / (*count2.get(&match2.get_prefix()).expect("found") as f64);
-        model2.prob.insert(*match2, prob);
+    // pruning
+    let mut report = pinyin::PruneReport::new(3);
+    let min_count = |order| {
+        opt.min_count
+            .iter()
+            .filter(|min_count| min_count.order == order)
+            .map(|min_count| min_count.count)
+            .max()
+            .unwrap_or(0)
+    };
+    if min_count(3) > 1 {
+        let ngrams: Vec<pinyin::Match3> = counted.below(min_count(3), &vocab);
+        pinyin::prune_ngrams(&[&model1], &mut model2, &mut model3, &ngrams, &mut report);
+    }
+    if min_count(2) > 1 {
+        // bigrams extended by a trigram are kept as its context
+        let contexts = model3.contexts();
+        let ngrams: Vec<pinyin::Match2> = counted
+            .below(min_count(2), &vocab)
+            .into_iter()
+            .filter(|ngram: &pinyin::Match2| !contexts.contains(ngram.chars()))
+            .collect();
+        pinyin::prune_ngrams(&[], &mut model1, &mut model2, &ngrams, &mut report);
+        pinyin::renormalize(&[&model1], &mut model2, &model3);
     }
 
-    for (match3, o) in &occur3 {
-        let prob = (*o as f64) / (*count3.get(&match3.get_prefix()).expect("found") as f64);
-        model3.prob.insert(*match3, prob);
+    drop(temp_dir);
+
+    if let Some(size) = opt.prune_size {
+        pinyin::prune_to_size(&mut model1, &mut model2, &mut model3, size, &mut report);
+    }
+    if report.total() > 0 {
+        println!(
+            "Pruned {} bigrams and {} trigrams, {} n-grams left, \
+             estimated perplexity increase {:.2}%",
+            report.removed[1],
+            report.removed[2],
+            model1.len() + model2.len() + model3.len(),
+            report.perplexity_increase() * 100.0
+        );
     }
 
-    println!("Saving...");
-    model1.save();
-    model2.save();
-    model3.save();
+    println!("Saving to {:?}...", opt.output_dir);
+    fs::create_dir_all(&opt.output_dir).expect("create output dir");
+    model1
+        .save_to(
+            opt.output_dir
+                .join(pinyin::Model::<pinyin::Match1>::file_name()),
+        )
+        .expect("save model1");
+    model2
+        .save_to(
+            opt.output_dir
+                .join(pinyin::Model::<pinyin::Match2>::file_name()),
+        )
+        .expect("save model2");
+    model3
+        .save_to(
+            opt.output_dir
+                .join(pinyin::Model::<pinyin::Match3>::file_name()),
+        )
+        .expect("save model3");
 }
@@ -0,0 +1,476 @@
+//! Compact binary model format
+//!
+//! All integers and floats are big-endian, so that n-gram keys compare
+//! bytewise in the same order as their char ids:
+//!
+//! ```text
+//! magic          4 bytes, "PYBM"
+//! version        u32
+//! order          u32
+//! vocab size     u32
+//! n-gram count   u64
+//! prob min/step  f32, f32    log prob = min + quantized * step
+//! backoff min/step  f32, f32
+//! mapping size   u64
+//! metadata size  u32
+//! checksum       u32, CRC-32 of all other bytes of the file
+//! vocab          vocab size * u32, sorted chars, a char's id is its index
+//! mapping        JSON object of syllable to chars
+//! metadata       JSON object, see `Metadata`
+//! n-grams        n-gram count * (order * u16 char ids, u16 prob, u16 backoff),
+//!                sorted by char ids
+//! ```
+//!
+//! A quantized value of `u16::MAX` means the n-gram has no such value.
+
+use super::*;
+use memmap2::Mmap;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::ops::Deref;
+
+pub const BINARY_MAGIC: [u8; 4] = *b"PYBM";
+pub const BINARY_VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 56;
+const CHECKSUM_OFFSET: usize = 52;
+const MISSING: u16 = u16::MAX;
+
+enum Bytes {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Mapped(mmap) => mmap,
+            Bytes::Owned(vec) => vec,
+        }
+    }
+}
+
+/// Linear quantization of log values into `0..MISSING`
+#[derive(Debug, Clone, Copy)]
+struct Quantizer {
+    min: f32,
+    step: f32,
+}
+
+/// Log of `value`, floored so that zero weights stay finite
+fn floored_ln(value: f64) -> f64 {
+    value.ln().max(UNSEEN_PROB.ln())
+}
+
+impl Quantizer {
+    fn new<'a, I: Iterator<Item = &'a f64>>(values: I) -> Self {
+        let mut min = f64::INFINITY;
+        let mut max = f64::NEG_INFINITY;
+        for &value in values {
+            min = min.min(floored_ln(value));
+            max = max.max(floored_ln(value));
+        }
+        if min > max {
+            return Quantizer {
+                min: 0.0,
+                step: 0.0,
+            };
+        }
+        Quantizer {
+            min: min as f32,
+            step: ((max - min) / (MISSING - 1) as f64) as f32,
+        }
+    }
+
+    fn quantize(&self, value: Option<&f64>) -> u16 {
+        match value {
+            Some(value) if self.step > 0.0 => {
+                let q = ((floored_ln(*value) - self.min as f64) / self.step as f64).round();
+                q.clamp(0.0, (MISSING - 1) as f64) as u16
+            }
+            Some(_) => 0,
+            None => MISSING,
+        }
+    }
+
+    /// Log of the original value
+    fn dequantize(&self, q: u16) -> Option<f64> {
+        if q == MISSING {
+            None
+        } else {
+            Some(self.min as f64 + q as f64 * self.step as f64)
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_f32(data: &[u8], offset: usize) -> f32 {
+    f32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+/// CRC-32 of `data` without the checksum field
+fn checksum(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data[..CHECKSUM_OFFSET]);
+    hasher.update(&data[HEADER_LEN..]);
+    hasher.finalize()
+}
+
+/// A model in the binary format, looked up in place without parsing
+/// the n-grams, usually from a memory-mapped file
+pub struct BinaryModel {
+    data: Bytes,
+    order: usize,
+    metadata: Metadata,
+    vocab: Vocab,
+    mapping: BTreeMap<String, Vec<char>>,
+    count: usize,
+    offset: usize,
+    prob: Quantizer,
+    backoff: Quantizer,
+}
+
+impl BinaryModel {
+    /// Default file name of a model of `order`, e.g. `model3.bin`
+    pub fn file_name(order: usize) -> String {
+        format!("model{}.bin", order)
+    }
+
+    /// Memory-map a model file
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // the file must not be modified while mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::from_bytes(Bytes::Mapped(mmap))
+    }
+
+    /// Read a whole model into memory
+    pub fn load_from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(Bytes::Owned(data))
+    }
+
+    fn from_bytes(data: Bytes) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidModel(reason.to_string());
+        if data.len() < HEADER_LEN || data[..4] != BINARY_MAGIC {
+            return Err(invalid("not a binary model"));
+        }
+        let version = read_u32(&data, 4);
+        if version != BINARY_VERSION {
+            return Err(Error::InvalidModel(format!(
+                "binary model version {}, expected {}",
+                version, BINARY_VERSION
+            )));
+        }
+        let order = read_u32(&data, 8) as usize;
+        let vocab_size = read_u32(&data, 12) as usize;
+        let count = read_u64(&data, 16) as usize;
+        let prob = Quantizer {
+            min: read_f32(&data, 24),
+            step: read_f32(&data, 28),
+        };
+        let backoff = Quantizer {
+            min: read_f32(&data, 32),
+            step: read_f32(&data, 36),
+        };
+        let mapping_len = read_u64(&data, 40) as usize;
+        let metadata_len = read_u32(&data, 48) as usize;
+
+        // sizes come from the file, a corrupted one must not overflow them
+        let truncated = || invalid("truncated binary model");
+        check_order(order).map_err(|_| invalid("unsupported order"))?;
+        let mapping_offset = vocab_size
+            .checked_mul(4)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .ok_or_else(truncated)?;
+        let metadata_offset = mapping_offset
+            .checked_add(mapping_len)
+            .ok_or_else(truncated)?;
+        let offset = metadata_offset
+            .checked_add(metadata_len)
+            .ok_or_else(truncated)?;
+        let end = count
+            .checked_mul(order * 2 + 4)
+            .and_then(|len| len.checked_add(offset))
+            .ok_or_else(truncated)?;
+        if data.len() != end {
+            return Err(truncated());
+        }
+        if read_u32(&data, CHECKSUM_OFFSET) != checksum(&data) {
+            return Err(invalid("checksum mismatch, the file is corrupted"));
+        }
+        let mut vocab = Vocab::new();
+        for i in 0..vocab_size {
+            let ch = std::char::from_u32(read_u32(&data, HEADER_LEN + i * 4))
+                .ok_or_else(|| invalid("invalid char in vocabulary"))?;
+            if vocab.intern(ch)? as usize != i {
+                return Err(invalid("duplicate char in vocabulary"));
+            }
+        }
+        let mapping = serde_json::from_slice(&data[mapping_offset..metadata_offset])?;
+        let metadata: Metadata = serde_json::from_slice(&data[metadata_offset..offset])?;
+        metadata.validate(order)?;
+
+        Ok(BinaryModel {
+            data,
+            order,
+            metadata,
+            vocab,
+            mapping,
+            count,
+            offset,
+            prob,
+            backoff,
+        })
+    }
+
+    fn entry(&self, index: usize) -> &[u8] {
+        let len = self.order * 2 + 4;
+        let start = self.offset + index * len;
+        &self.data[start..start + len]
+    }
+
+    /// Quantized prob and backoff weight of the n-gram made of `chars`
+    fn lookup(&self, chars: &[char]) -> Option<(u16, u16)> {
+        let mut key = Vec::with_capacity(chars.len() * 2);
+        for ch in chars {
+            let id = self.vocab.id(*ch)? as u16;
+            key.extend_from_slice(&id.to_be_bytes());
+        }
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let entry = self.entry(mid);
+            match entry[..key.len()].cmp(&key) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => {
+                    return Some((read_u16(entry, key.len()), read_u16(entry, key.len() + 2)))
+                }
+            }
+        }
+        None
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Write `json_model` in the binary format, all its n-grams must be of
+    /// the same order
+    pub fn write_json<W: Write>(json_model: &JsonModel, mut writer: W) -> Result<(), Error> {
+        let mut order = None;
+        let mut keys: BTreeSet<Vec<char>> = BTreeSet::new();
+        let mut vocab: BTreeSet<char> = BTreeSet::new();
+        for key in json_model.prob.keys().chain(json_model.backoff.keys()) {
+            let chars: Vec<char> = key.chars().collect();
+            if *order.get_or_insert(chars.len()) != chars.len() {
+                return Err(Error::InvalidModel(format!(
+                    "n-grams of different orders: {:?}",
+                    key
+                )));
+            }
+            vocab.extend(chars.iter());
+            keys.insert(chars);
+        }
+        for chars in json_model.mapping.values() {
+            vocab.extend(chars.iter());
+        }
+        let order = order.ok_or_else(|| Error::InvalidModel("empty model".to_string()))?;
+        json_model.validate(order)?;
+        if vocab.len() > MISSING as usize {
+            return Err(Error::InvalidModel(format!(
+                "{} chars do not fit into 16 bit ids",
+                vocab.len()
+            )));
+        }
+        let vocab: Vec<char> = vocab.into_iter().collect();
+        let mapping = serde_json::to_vec(&json_model.mapping)?;
+        let metadata = Metadata {
+            vocab_size: vocab.len(),
+            ..json_model.metadata.clone().unwrap_or_else(|| Metadata {
+                created: 0,
+                ..Metadata::new(order)
+            })
+        };
+        let metadata = serde_json::to_vec(&metadata)?;
+        let prob = Quantizer::new(json_model.prob.values());
+        let backoff = Quantizer::new(json_model.backoff.values());
+
+        // built in memory to fill in the checksum
+        let mut data = Vec::new();
+        data.extend_from_slice(&BINARY_MAGIC);
+        data.extend_from_slice(&BINARY_VERSION.to_be_bytes());
+        data.extend_from_slice(&(order as u32).to_be_bytes());
+        data.extend_from_slice(&(vocab.len() as u32).to_be_bytes());
+        data.extend_from_slice(&(keys.len() as u64).to_be_bytes());
+        for value in &[prob.min, prob.step, backoff.min, backoff.step] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&(mapping.len() as u64).to_be_bytes());
+        data.extend_from_slice(&(metadata.len() as u32).to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        for ch in &vocab {
+            data.extend_from_slice(&(*ch as u32).to_be_bytes());
+        }
+        data.extend_from_slice(&mapping);
+        data.extend_from_slice(&metadata);
+        // chars are sorted like their ids, so are the keys
+        for chars in &keys {
+            for ch in chars {
+                let id = vocab.binary_search(ch).unwrap() as u16;
+                data.extend_from_slice(&id.to_be_bytes());
+            }
+            let key: String = chars.iter().collect();
+            data.extend_from_slice(&prob.quantize(json_model.prob.get(&key)).to_be_bytes());
+            data.extend_from_slice(&backoff.quantize(json_model.backoff.get(&key)).to_be_bytes());
+        }
+        let checksum = checksum(&data);
+        data[CHECKSUM_OFFSET..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+
+        writer.write_all(&data)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Convert back to a JSON model, probabilities keep their quantization error
+    pub fn to_json(&self) -> Result<JsonModel, Error> {
+        let mut json_model = JsonModel {
+            metadata: Some(self.metadata.clone()),
+            checksum: None,
+            mapping: self.mapping.clone(),
+            prob: BTreeMap::new(),
+            backoff: BTreeMap::new(),
+        };
+        for index in 0..self.count {
+            let entry = self.entry(index);
+            let key: String = (0..self.order)
+                .map(|i| self.vocab.char(read_u16(entry, i * 2) as u32))
+                .collect();
+            let offset = self.order * 2;
+            if let Some(log_prob) = self.prob.dequantize(read_u16(entry, offset)) {
+                json_model.prob.insert(key.clone(), log_prob.exp());
+            }
+            if let Some(log_weight) = self.backoff.dequantize(read_u16(entry, offset + 2)) {
+                json_model.backoff.insert(key, log_weight.exp());
+            }
+        }
+        json_model.update_checksum()?;
+        Ok(json_model)
+    }
+}
+
+impl LanguageModel for BinaryModel {
+    fn order(&self) -> usize {
+        self.order
+    }
+
+    fn mapping(&self) -> &BTreeMap<String, Vec<char>> {
+        &self.mapping
+    }
+
+    fn log_prob(&self, history: &[char], ch: char) -> Option<f64> {
+        if history.len() + 1 < self.order {
+            return None;
+        }
+        let mut chars = history[history.len() + 1 - self.order..].to_vec();
+        chars.push(ch);
+        let (prob, _) = self.lookup(&chars)?;
+        self.prob.dequantize(prob)
+    }
+
+    fn backoff(&self, context: &[char]) -> Option<f64> {
+        if context.len() != self.order {
+            return None;
+        }
+        let (_, backoff) = self.lookup(context)?;
+        self.backoff.dequantize(backoff)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        Some(&self.metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn bigrams() -> Vec<u8> {
+        bigrams_with_backoff(0.5)
+    }
+
+    fn bigrams_with_backoff(backoff: f64) -> Vec<u8> {
+        let mut model = model::<Match2>(&[("xy", 0.6), ("yx", 0.1)]);
+        model
+            .insert_backoff(&Match2::from_chars(&['x', 'y']), backoff)
+            .unwrap();
+        let mut data = Vec::new();
+        BinaryModel::write_json(&model.to_json().unwrap(), &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let model = BinaryModel::load_from_reader(&bigrams()[..]).unwrap();
+        assert_eq!(model.order(), 2);
+        assert!((model.log_prob(&['x'], 'y').unwrap() - 0.6f64.ln()).abs() < 1e-3);
+        assert!((model.log_prob(&['y'], 'x').unwrap() - 0.1f64.ln()).abs() < 1e-3);
+        assert_eq!(model.log_prob(&['x'], 'z'), None);
+        assert!((model.backoff(&['x', 'y']).unwrap() - 0.5f64.ln()).abs() < 1e-3);
+        assert_eq!(model.backoff(&['y', 'x']), None);
+
+        let json_model = model.to_json().unwrap();
+        assert_eq!(json_model.prob.len(), 2);
+        assert_eq!(json_model.backoff.len(), 1);
+        assert_eq!(json_model.mapping["a"], vec!['x', 'y', 'z']);
+    }
+
+    #[test]
+    fn rejects_corrupted_files() {
+        let mut data = bigrams();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(BinaryModel::load_from_reader(&data[..]).is_err());
+
+        let mut data = bigrams();
+        data.pop();
+        assert!(BinaryModel::load_from_reader(&data[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_sizes() {
+        let mut data = bigrams();
+        data[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(BinaryModel::load_from_reader(&data[..]).is_err());
+
+        let mut data = bigrams();
+        data[40..48].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(BinaryModel::load_from_reader(&data[..]).is_err());
+    }
+
+    #[test]
+    fn zero_weights_stay_finite() {
+        let model = BinaryModel::load_from_reader(&bigrams_with_backoff(0.0)[..]).unwrap();
+        assert!((model.log_prob(&['x'], 'y').unwrap() - 0.6f64.ln()).abs() < 1e-3);
+        assert!((model.log_prob(&['y'], 'x').unwrap() - 0.1f64.ln()).abs() < 1e-3);
+        let backoff = model.backoff(&['x', 'y']).unwrap();
+        assert!((backoff - UNSEEN_PROB.ln()).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+/// Which width punctuation is normalized to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Punctuation {
+    /// Leave punctuation as is
+    Keep,
+    /// ASCII punctuation, e.g. "，" becomes ","
+    HalfWidth,
+    /// Full-width punctuation, e.g. "," becomes "，"
+    FullWidth,
+}
+
+impl FromStr for Punctuation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Punctuation::Keep),
+            "half" => Ok(Punctuation::HalfWidth),
+            "full" => Ok(Punctuation::FullWidth),
+            _ => Err(format!("unknown punctuation width {:?}", s)),
+        }
+    }
+}
+
+/// Tags that separate blocks of text, replaced by a line break so that the
+/// text before and after them is not counted as one run
+const BLOCK_TAGS: [&str; 22] = [
+    "address",
+    "article",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dt",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "p",
+    "section",
+    "table",
+    "td",
+    "tr",
+];
+
+/// Elements whose content is not text
+const SKIPPED_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Cleans scraped text before counting
+#[derive(Debug, Clone)]
+pub struct TextCleaner {
+    /// Remove HTML tags, comments, scripts and styles
+    pub strip_tags: bool,
+    /// Decode HTML entities like `&amp;` and `&#20013;`
+    pub decode_entities: bool,
+    pub punctuation: Punctuation,
+}
+
+impl Default for TextCleaner {
+    fn default() -> Self {
+        TextCleaner {
+            strip_tags: true,
+            decode_entities: true,
+            punctuation: Punctuation::HalfWidth,
+        }
+    }
+}
+
+impl TextCleaner {
+    pub fn clean(&self, text: &str) -> String {
+        let mut text = if self.strip_tags {
+            strip_tags(text)
+        } else {
+            text.to_string()
+        };
+        if self.decode_entities {
+            text = decode_entities(&text);
+        }
+        match self.punctuation {
+            Punctuation::Keep => text,
+            Punctuation::HalfWidth => text.chars().map(half_width).collect(),
+            Punctuation::FullWidth => text.chars().map(full_width).collect(),
+        }
+    }
+}
+
+/// Name of the tag in `tag`, the text between '<' and '>', lowercased
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('/')
+        .chars()
+        .take_while(|ch| ch.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Byte position of the lowercase ASCII `needle` in `haystack`, ignoring
+/// ASCII case
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Remove markup, replacing block tags by line breaks
+pub fn strip_tags(html: &str) -> String {
+    let mut res = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let end = match rest.find('>') {
+            Some(end) => end,
+            // a lone '<' is text
+            None => break,
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        let name = tag_name(tag);
+        if SKIPPED_ELEMENTS.contains(&name.as_str()) && !tag.starts_with('/') {
+            let close = format!("</{}", name);
+            rest = match find_ignore_case(rest, &close) {
+                Some(pos) => &rest[pos..],
+                None => "",
+            };
+        } else if BLOCK_TAGS.contains(&name.as_str()) {
+            res.push('\n');
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Decode named and numeric character references, unknown ones are kept
+pub fn decode_entities(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|ch| (ch, end + 2)));
+        match decoded {
+            Some((ch, len)) => {
+                res.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// The char of an entity without its '&' and ';'
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return std::char::from_u32(code);
+    }
+    let ch = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "middot" => '·',
+        "times" => '×',
+        _ => return None,
+    };
+    Some(ch)
+}
+
+/// Half-width form of full-width ASCII and common CJK punctuation
+fn half_width(ch: char) -> char {
+    match ch {
+        '\u{3000}' => ' ',
+        '。' => '.',
+        '、' => ',',
+        '\u{ff01}'..='\u{ff5e}' => std::char::from_u32(ch as u32 - 0xfee0).unwrap_or(ch),
+        _ => ch,
+    }
+}
+
+/// Full-width form of ASCII punctuation, letters and digits are kept
+fn full_width(ch: char) -> char {
+    if ch.is_ascii_punctuation() {
+        std::char::from_u32(ch as u32 + 0xfee0).unwrap_or(ch)
+    } else {
+        ch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_and_comments() {
+        assert_eq!(
+            strip_tags("<p>中文</p><div>好<br/>的</div>"),
+            "\n中文\n\n好\n的\n"
+        );
+        assert_eq!(strip_tags("<b class=\"x\">粗</B>体"), "粗体");
+        assert_eq!(strip_tags("前<SCRIPT>var p = '<p>';</Script>后"), "前后");
+        assert_eq!(strip_tags("前<style>p {}"), "前");
+        assert_eq!(strip_tags("一<!-- 注释 <p> -->二<!-- 三"), "一二");
+        assert_eq!(strip_tags("a < b"), "a < b");
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            decode_entities("&amp;&lt;&#20013;&#x4e2D;&ldquo;&unknown;&"),
+            "&<中中“&unknown;&"
+        );
+        assert_eq!(
+            decode_entities("&#xffffffff;&verylongname;"),
+            "&#xffffffff;&verylongname;"
+        );
+    }
+
+    #[test]
+    fn punctuation_width() {
+        let mut cleaner = TextCleaner::default();
+        assert_eq!(cleaner.clean("你好，世界！ＡＢ。<p>"), "你好,世界!AB.\n");
+        cleaner.punctuation = Punctuation::FullWidth;
+        assert_eq!(cleaner.clean("a,b!"), "a，b！");
+        cleaner.punctuation = Punctuation::Keep;
+        cleaner.strip_tags = false;
+        cleaner.decode_entities = false;
+        assert_eq!(cleaner.clean("，<p>&amp;"), "，<p>&amp;");
+    }
+}
@@ -0,0 +1,427 @@
+use super::*;
+use encoding_rs::GBK;
+use flate2::read::MultiGzDecoder;
+use serde_json::Value;
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// Text encoding of a corpus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Gbk,
+    /// UTF-8 where valid, GBK otherwise, decided line by line
+    Auto,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "gbk" | "gb2312" => Ok(Encoding::Gbk),
+            "auto" => Ok(Encoding::Auto),
+            _ => Err(format!("unknown encoding {:?}", s)),
+        }
+    }
+}
+
+impl Encoding {
+    /// Decode `bytes`, replacing malformed sequences
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Gbk => GBK.decode(bytes).0.into_owned(),
+            Encoding::Auto => match std::str::from_utf8(bytes) {
+                Ok(text) => text.to_string(),
+                Err(_) => GBK.decode(bytes).0.into_owned(),
+            },
+        }
+    }
+}
+
+/// Layout of the lines of a corpus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorpusFormat {
+    /// Every line is a text
+    Plain,
+    /// Every line is a JSON value, the text is the string at the path of
+    /// object keys or array indices
+    JsonLines(Vec<String>),
+    /// Sina news articles, JSON objects with `html`, `time`, `title` and `url`,
+    /// the title is a text of its own before the html if `titles` is set
+    News { titles: bool },
+}
+
+impl FromStr for CorpusFormat {
+    type Err = String;
+
+    /// Parse `plain`, `news` or `jsonl[:field.path]`, the path defaults to `text`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().unwrap_or(""), parts.next()) {
+            ("plain", None) => Ok(CorpusFormat::Plain),
+            ("news", None) => Ok(CorpusFormat::News { titles: false }),
+            ("jsonl", path) => Ok(CorpusFormat::JsonLines(
+                path.unwrap_or("text")
+                    .split('.')
+                    .map(String::from)
+                    .collect(),
+            )),
+            _ => Err(format!("unknown corpus format {:?}", s)),
+        }
+    }
+}
+
+/// Yields the texts of a corpus one by one
+pub trait CorpusReader {
+    /// The next text, `None` at the end of the corpus
+    fn next_text(&mut self) -> Result<Option<String>, Error>;
+}
+
+/// Decoded lines of a corpus, line terminators removed
+pub(crate) struct Lines<R> {
+    reader: R,
+    encoding: Encoding,
+    buf: Vec<u8>,
+    /// Number of the line returned last, starting at 1
+    pub(crate) number: usize,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub(crate) fn new(reader: R, encoding: Encoding) -> Self {
+        Lines {
+            reader,
+            encoding,
+            buf: Vec::new(),
+            number: 0,
+        }
+    }
+
+    /// The next line that is not blank
+    pub(crate) fn next_line(&mut self) -> Result<Option<String>, Error> {
+        loop {
+            self.buf.clear();
+            // GBK trail bytes are never '\n', so lines can be decoded one by one
+            if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            self.number += 1;
+            let line = self.encoding.decode(&self.buf);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !line.trim().is_empty() {
+                return Ok(Some(line.to_string()));
+            }
+        }
+    }
+
+    fn invalid(&self, reason: String) -> Error {
+        Error::InvalidCorpus {
+            line: self.number,
+            reason,
+        }
+    }
+}
+
+/// One text per line
+pub struct PlainText<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> PlainText<R> {
+    pub fn new(reader: R, encoding: Encoding) -> Self {
+        PlainText {
+            lines: Lines::new(reader, encoding),
+        }
+    }
+}
+
+impl<R: BufRead> CorpusReader for PlainText<R> {
+    fn next_text(&mut self) -> Result<Option<String>, Error> {
+        self.lines.next_line()
+    }
+}
+
+/// One JSON value per line, the text at `path` inside it
+pub struct JsonLines<R> {
+    lines: Lines<R>,
+    path: Vec<String>,
+}
+
+impl<R: BufRead> JsonLines<R> {
+    pub fn new(reader: R, encoding: Encoding, path: Vec<String>) -> Self {
+        JsonLines {
+            lines: Lines::new(reader, encoding),
+            path,
+        }
+    }
+}
+
+impl<R: BufRead> CorpusReader for JsonLines<R> {
+    fn next_text(&mut self) -> Result<Option<String>, Error> {
+        let line = match self.lines.next_line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let value: Value = serde_json::from_str(&line)
+            .map_err(|err| self.lines.invalid(format!("invalid json: {}", err)))?;
+        let mut cur = &value;
+        for key in &self.path {
+            let next = match cur {
+                Value::Array(values) => key.parse::<usize>().ok().and_then(|i| values.get(i)),
+                _ => cur.get(key),
+            };
+            cur = next.ok_or_else(|| {
+                self.lines
+                    .invalid(format!("no field {:?}", self.path.join(".")))
+            })?;
+        }
+        match cur {
+            Value::String(text) => Ok(Some(text.clone())),
+            _ => Err(self
+                .lines
+                .invalid(format!("field {:?} is not a string", self.path.join(".")))),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Article {
+    html: String,
+    #[allow(dead_code)]
+    time: String,
+    title: String,
+    #[allow(dead_code)]
+    url: String,
+}
+
+/// Sina news articles, one JSON object per line, the text is their `html`,
+/// preceded by their `title` if `titles` is set
+pub struct News<R> {
+    lines: Lines<R>,
+    titles: bool,
+    /// html of the article whose title was returned last
+    pending: Option<String>,
+}
+
+impl<R: BufRead> News<R> {
+    pub fn new(reader: R, encoding: Encoding, titles: bool) -> Self {
+        News {
+            lines: Lines::new(reader, encoding),
+            titles,
+            pending: None,
+        }
+    }
+}
+
+impl<R: BufRead> CorpusReader for News<R> {
+    fn next_text(&mut self) -> Result<Option<String>, Error> {
+        if let Some(html) = self.pending.take() {
+            return Ok(Some(html));
+        }
+        let line = match self.lines.next_line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let article: Article = serde_json::from_str(&line)
+            .map_err(|err| self.lines.invalid(format!("invalid article: {}", err)))?;
+        if self.titles && !article.title.trim().is_empty() {
+            self.pending = Some(article.html);
+            return Ok(Some(article.title));
+        }
+        Ok(Some(article.html))
+    }
+}
+
+/// `reader` decompressed if it is gzipped
+fn decompressed<R: Read + 'static>(reader: R) -> Result<Box<dyn BufRead>, Error> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Read a corpus of `format` from `reader`, decompressing it first if it
+/// is gzipped
+pub fn open_corpus<R: Read + 'static>(
+    reader: R,
+    format: &CorpusFormat,
+    encoding: Encoding,
+) -> Result<Box<dyn CorpusReader>, Error> {
+    Ok(reader_of(decompressed(reader)?, format, encoding))
+}
+
+fn reader_of<'a, R: BufRead + 'a>(
+    reader: R,
+    format: &CorpusFormat,
+    encoding: Encoding,
+) -> Box<dyn CorpusReader + 'a> {
+    match format {
+        CorpusFormat::Plain => Box::new(PlainText::new(reader, encoding)),
+        CorpusFormat::JsonLines(path) => Box::new(JsonLines::new(reader, encoding, path.clone())),
+        CorpusFormat::News { titles } => Box::new(News::new(reader, encoding, *titles)),
+    }
+}
+
+/// Undecoded lines of a corpus, read in chunks so that decoding and parsing
+/// them can be spread over threads, see `CorpusChunk::texts`
+pub struct CorpusChunks {
+    reader: Box<dyn BufRead>,
+    lines: usize,
+}
+
+impl CorpusChunks {
+    /// Read `reader`, decompressing it first if it is gzipped
+    pub fn open<R: Read + 'static>(reader: R) -> Result<Self, Error> {
+        Ok(CorpusChunks {
+            reader: decompressed(reader)?,
+            lines: 0,
+        })
+    }
+
+    /// Whole lines adding up to at least `bytes` bytes, fewer at the end of
+    /// the corpus, `None` after it
+    pub fn next_chunk(&mut self, bytes: usize) -> Result<Option<CorpusChunk>, Error> {
+        let mut data = Vec::new();
+        let lines_before = self.lines;
+        while data.len() < bytes && self.reader.read_until(b'\n', &mut data)? > 0 {
+            self.lines += 1;
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(CorpusChunk { data, lines_before }))
+    }
+}
+
+/// Undecoded whole lines of a corpus
+pub struct CorpusChunk {
+    data: Vec<u8>,
+    /// Number of lines of the corpus before the chunk
+    lines_before: usize,
+}
+
+impl CorpusChunk {
+    /// Size of the chunk in bytes
+    pub fn bytes(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Read the texts of the chunk, errors give line numbers of the whole
+    /// corpus
+    pub fn texts(&self, format: &CorpusFormat, encoding: Encoding) -> ChunkTexts<'_> {
+        ChunkTexts {
+            reader: reader_of(&self.data[..], format, encoding),
+            lines_before: self.lines_before,
+        }
+    }
+}
+
+/// Texts of a `CorpusChunk`
+pub struct ChunkTexts<'a> {
+    reader: Box<dyn CorpusReader + 'a>,
+    lines_before: usize,
+}
+
+impl CorpusReader for ChunkTexts<'_> {
+    fn next_text(&mut self) -> Result<Option<String>, Error> {
+        self.reader.next_text().map_err(|err| match err {
+            Error::InvalidCorpus { line, reason } => Error::InvalidCorpus {
+                line: line + self.lines_before,
+                reason,
+            },
+            err => err,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(reader: &mut dyn CorpusReader) -> Result<Vec<String>, Error> {
+        let mut texts = Vec::new();
+        while let Some(text) = reader.next_text()? {
+            texts.push(text);
+        }
+        Ok(texts)
+    }
+
+    fn article(title: &str, html: &str) -> String {
+        format!(
+            r#"{{"html":"{}","time":"","title":"{}","url":""}}"#,
+            html, title
+        )
+    }
+
+    #[test]
+    fn json_paths_index_arrays() {
+        let text = "{\"a\":[{\"t\":\"x\"},{\"t\":\"y\"}]}\n\n{\"a\":[{\"t\":\"z\"}]}\n";
+        let format: CorpusFormat = "jsonl:a.0.t".parse().unwrap();
+        let mut reader = reader_of(text.as_bytes(), &format, Encoding::Utf8);
+        assert_eq!(read_all(&mut *reader).unwrap(), vec!["x", "z"]);
+
+        let format: CorpusFormat = "jsonl:a.1.t".parse().unwrap();
+        let mut reader = reader_of(text.as_bytes(), &format, Encoding::Utf8);
+        assert_eq!(reader.next_text().unwrap(), Some("y".to_string()));
+        // the blank line is counted
+        assert!(matches!(
+            reader.next_text(),
+            Err(Error::InvalidCorpus { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn gzipped_corpora_are_detected() {
+        let text = "第一行\n第二行\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        for data in &[gzipped, text.as_bytes().to_vec()] {
+            let mut reader = open_corpus(
+                Cursor::new(data.clone()),
+                &CorpusFormat::Plain,
+                Encoding::Auto,
+            )
+            .unwrap();
+            assert_eq!(read_all(&mut *reader).unwrap(), vec!["第一行", "第二行"]);
+        }
+    }
+
+    #[test]
+    fn news_titles_come_before_their_html() {
+        let text = format!("{}\n{}\n", article("标题", "正文"), article(" ", "内容"));
+        let mut reader = News::new(text.as_bytes(), Encoding::Utf8, true);
+        // blank titles are skipped
+        assert_eq!(read_all(&mut reader).unwrap(), vec!["标题", "正文", "内容"]);
+        let mut reader = News::new(text.as_bytes(), Encoding::Utf8, false);
+        assert_eq!(read_all(&mut reader).unwrap(), vec!["正文", "内容"]);
+    }
+
+    #[test]
+    fn chunk_errors_give_corpus_lines() {
+        let text = format!(
+            "{}\n\n{}\n{}\nbad\n",
+            article("", "一"),
+            article("", "二"),
+            article("", "三")
+        );
+        let format = CorpusFormat::News { titles: false };
+        let mut chunks = CorpusChunks::open(Cursor::new(text.into_bytes())).unwrap();
+        let mut texts = Vec::new();
+        let mut error = None;
+        // one line per chunk
+        while let Some(chunk) = chunks.next_chunk(1).unwrap() {
+            match read_all(&mut chunk.texts(&format, Encoding::Utf8)) {
+                Ok(chunk_texts) => texts.extend(chunk_texts),
+                Err(err) => error = Some(err),
+            }
+        }
+        assert_eq!(texts, vec!["一", "二", "三"]);
+        assert!(matches!(error, Some(Error::InvalidCorpus { line: 5, .. })));
+    }
+}
@@ -0,0 +1,271 @@
+use super::*;
+
+/// Raw counts of the n-grams of every order up to `order()`, keyed by
+/// `Vocab::key`
+///
+/// Tables counted over separate parts of a corpus with the same vocabulary
+/// add up with `merge`.
+#[derive(Debug, Clone)]
+pub struct Counts {
+    tables: Vec<HashMap<u64, u32>>,
+}
+
+impl Counts {
+    /// Fails if n-grams of `order` do not fit into a packed key
+    pub fn new(order: usize) -> Result<Self, Error> {
+        check_order(order)?;
+        Ok(Counts {
+            tables: vec![HashMap::new(); order],
+        })
+    }
+
+    pub fn order(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Counts of the n-grams of `order`
+    pub fn table(&self, order: usize) -> &HashMap<u64, u32> {
+        &self.tables[order - 1]
+    }
+
+    /// Remove the counts of the n-grams of `order`
+    pub fn take_table(&mut self, order: usize) -> HashMap<u64, u32> {
+        std::mem::take(&mut self.tables[order - 1])
+    }
+
+    /// Counts of the n-grams of `order` sorted by key, so that n-grams with
+    /// the same prefix are adjacent
+    pub fn sorted(&self, order: usize) -> Vec<(u64, u32)> {
+        let mut entries: Vec<(u64, u32)> = self
+            .table(order)
+            .iter()
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    /// Rough number of bytes taken by the tables
+    pub fn memory(&self) -> usize {
+        // a key, a count and a control byte per bucket
+        self.tables
+            .iter()
+            .map(|table| table.capacity() * (std::mem::size_of::<(u64, u32)>() + 1))
+            .sum()
+    }
+
+    /// Count every n-gram of consecutive chars of `vocab` in `text`, all
+    /// orders in a single pass
+    pub fn add_text(&mut self, text: &str, vocab: &Vocab) {
+        let order = self.order();
+        // ids of the last `len` chars, most recent last
+        let mut window = vec![0; order];
+        let mut len = 0;
+        for ch in text.chars() {
+            let id = match vocab.id(ch) {
+                Some(id) => id,
+                None => {
+                    len = 0;
+                    continue;
+                }
+            };
+            window.rotate_left(1);
+            window[order - 1] = id;
+            len = (len + 1).min(order);
+            let mut key = 0;
+            for n in 1..=len {
+                key |= (window[order - n] as u64) << ((n - 1) as u32 * ID_BITS);
+                *self.tables[n - 1].entry(key).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Add the counts of `other`, which must use the same vocabulary
+    pub fn merge(&mut self, other: Counts) {
+        assert_eq!(self.order(), other.order());
+        for (table, other) in self.tables.iter_mut().zip(other.tables) {
+            if table.len() < other.len() {
+                let smaller = std::mem::replace(table, other);
+                merge_table(table, smaller);
+            } else {
+                merge_table(table, other);
+            }
+        }
+    }
+
+    /// Add the counts of `json_counts` of the orders of this table, returning
+    /// the number of n-grams dropped because a char is not in `vocab`
+    pub fn add_json(&mut self, json_counts: &JsonCounts, vocab: &Vocab) -> usize {
+        let mut dropped = 0;
+        for (table, counts) in self.tables.iter_mut().zip(&json_counts.counts) {
+            for (ngram, count) in counts {
+                match vocab.key(ngram.chars()) {
+                    Some(key) => *table.entry(key).or_insert(0) += count,
+                    None => dropped += 1,
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Counts of the n-grams of `order` by their chars
+    pub fn to_chars(&self, order: usize, vocab: &Vocab) -> BTreeMap<Vec<char>, u32> {
+        self.table(order)
+            .iter()
+            .map(|(key, count)| (vocab.unpack(*key, order), *count))
+            .collect()
+    }
+}
+
+/// Raw n-gram counts by their chars, saved as gzipped JSON so that counts
+/// made with different vocabularies or on different machines can be merged
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JsonCounts {
+    /// Descriptions of the counted corpora
+    pub corpus: Vec<String>,
+    pub mapping: BTreeMap<String, Vec<char>>,
+    /// `counts[n - 1]` holds the counts of the n-grams
+    pub counts: Vec<BTreeMap<String, u32>>,
+}
+
+impl JsonCounts {
+    /// Default file name of a count file
+    pub fn file_name() -> String {
+        "counts.json.gz".to_string()
+    }
+
+    pub fn order(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::load_from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load gzipped JSON counts, failing if an n-gram is not of the order
+    /// of its table
+    pub fn load_from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        let json_counts: JsonCounts = serde_json::from_reader(GzDecoder::new(reader))?;
+        for (index, counts) in json_counts.counts.iter().enumerate() {
+            if let Some(key) = counts.keys().find(|key| key.chars().count() != index + 1) {
+                return Err(Error::InvalidModel(format!(
+                    "n-gram {:?} among the counts of order {}",
+                    key,
+                    index + 1
+                )));
+            }
+        }
+        Ok(json_counts)
+    }
+
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.save_to_writer(BufWriter::new(File::create(path)?))
+    }
+
+    /// Save as gzipped JSON
+    pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = GzEncoder::new(writer, Compression::default());
+        serde_json::to_writer(&mut writer, self)?;
+        writer.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Save counts as gzipped JSON like `save_to_writer`, reading
+    /// `tables[n - 1]` of the counts of the n-grams one by one instead of
+    /// holding them in memory, every n-gram must occur once in its table
+    pub fn save_streamed<W, I>(
+        corpus: &[String],
+        mapping: &BTreeMap<String, Vec<char>>,
+        tables: Vec<I>,
+        writer: W,
+    ) -> Result<(), Error>
+    where
+        W: Write,
+        I: Iterator<Item = (String, u32)>,
+    {
+        let mut writer = GzEncoder::new(writer, Compression::default());
+        writer.write_all(b"{\"corpus\":")?;
+        serde_json::to_writer(&mut writer, corpus)?;
+        writer.write_all(b",\"mapping\":")?;
+        serde_json::to_writer(&mut writer, mapping)?;
+        writer.write_all(b",\"counts\":[")?;
+        for (index, table) in tables.into_iter().enumerate() {
+            if index > 0 {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(b"{")?;
+            for (index, (ngram, count)) in table.enumerate() {
+                if index > 0 {
+                    writer.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut writer, &ngram)?;
+                write!(writer, ":{}", count)?;
+            }
+            writer.write_all(b"}")?;
+        }
+        writer.write_all(b"]}")?;
+        writer.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+fn merge_table(table: &mut HashMap<u64, u32>, other: HashMap<u64, u32>) {
+    for (key, count) in other {
+        *table.entry(key).or_insert(0) += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn table(counts: &[(&str, u32)]) -> BTreeMap<String, u32> {
+        counts
+            .iter()
+            .map(|(ngram, count)| (ngram.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn streamed_counts_load_back() {
+        let corpus = vec!["news".to_string()];
+        let mapping = mapping_of("xy");
+        let tables = vec![
+            table(&[("x", 3), ("y", 1)]),
+            // chars that JSON strings escape
+            table(&[("\"x", 1), ("xy", 2)]),
+            table(&[]),
+        ];
+        let mut data = Vec::new();
+        JsonCounts::save_streamed(
+            &corpus,
+            &mapping,
+            tables
+                .iter()
+                .map(|table| table.clone().into_iter())
+                .collect(),
+            &mut data,
+        )
+        .unwrap();
+        let loaded = JsonCounts::load_from_reader(&data[..]).unwrap();
+        assert_eq!(loaded.corpus, corpus);
+        assert_eq!(loaded.mapping, mapping);
+        assert_eq!(loaded.counts, tables);
+    }
+
+    #[test]
+    fn json_counts_outside_the_vocab_are_dropped() {
+        let vocab = Vocab::from_mapping(&mapping_of("xy")).unwrap();
+        let json_counts = JsonCounts {
+            counts: vec![table(&[("x", 3), ("z", 2)]), table(&[("xy", 2), ("zx", 1)])],
+            ..JsonCounts::default()
+        };
+        let mut counts = Counts::new(2).unwrap();
+        counts.add_json(&json_counts, &vocab);
+        assert_eq!(counts.add_json(&json_counts, &vocab), 2);
+        let bigram = vocab.key("xy".chars()).unwrap();
+        assert_eq!(counts.sorted(2), vec![(bigram, 4)]);
+        assert_eq!(counts.table(1).len(), 1);
+    }
+}
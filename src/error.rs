@@ -0,0 +1,79 @@
+use std::fmt::{self, Display};
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// `token` is not a pinyin syllable, `position` is its index among
+    /// the tokens of the input
+    UnknownSyllable {
+        token: String,
+        position: usize,
+    },
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A model file that is malformed or does not fit where it is used
+    InvalidModel(String),
+    /// `line` of a corpus file does not match the corpus format
+    InvalidCorpus {
+        line: usize,
+        reason: String,
+    },
+    /// `line` of a pinyin mapping file does not match the mapping format
+    InvalidMapping {
+        line: usize,
+        reason: String,
+    },
+    /// Smoothing parameters that do not make a distribution or do not fit
+    /// the models
+    InvalidSmoothing(String),
+    /// N-grams or a vocabulary beyond what packed n-gram keys can hold
+    TooLarge(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownSyllable { token, position } => {
+                write!(f, "unknown pinyin {:?} at position {}", token, position)
+            }
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Json(err) => write!(f, "invalid json model: {}", err),
+            Error::InvalidModel(reason) => write!(f, "invalid model: {}", reason),
+            Error::InvalidCorpus { line, reason } => {
+                write!(f, "invalid corpus at line {}: {}", line, reason)
+            }
+            Error::InvalidMapping { line, reason } => {
+                write!(f, "invalid mapping at line {}: {}", line, reason)
+            }
+            Error::InvalidSmoothing(reason) => write!(f, "invalid smoothing: {}", reason),
+            Error::TooLarge(reason) => write!(f, "too large: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::UnknownSyllable { .. }
+            | Error::InvalidModel(_)
+            | Error::InvalidCorpus { .. }
+            | Error::InvalidMapping { .. }
+            | Error::InvalidSmoothing(_)
+            | Error::TooLarge(_) => None,
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
@@ -0,0 +1,159 @@
+use super::*;
+use std::io::BufRead;
+
+/// How often the chars of one pinyin syllable were decoded
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct SyllableErrors {
+    pub total: usize,
+    pub wrong: usize,
+}
+
+impl SyllableErrors {
+    pub fn error_rate(&self) -> f64 {
+        self.wrong as f64 / self.total.max(1) as f64
+    }
+}
+
+/// Accuracy of a decoder on a test set of pinyin and the expected chinese
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Evaluation {
+    /// Number of expected chars
+    pub chars: usize,
+    /// Number of decoded chars equal to the expected one at the same position
+    pub correct_chars: usize,
+    pub sentences: usize,
+    pub correct_sentences: usize,
+    /// Sentences the decoder returned no result for
+    pub failed_sentences: usize,
+    /// Errors by the syllable the expected char was typed as
+    pub syllables: BTreeMap<String, SyllableErrors>,
+}
+
+impl Evaluation {
+    /// Compare `result` to `expected`, `syllables` are the syllables typed
+    /// for the expected chars if known
+    pub fn add(&mut self, syllables: Option<&[&str]>, result: &str, expected: &str) {
+        let result: Vec<char> = result.chars().collect();
+        let expected: Vec<char> = expected.chars().collect();
+        let correct: Vec<bool> = expected
+            .iter()
+            .enumerate()
+            .map(|(i, ch)| result.get(i) == Some(ch))
+            .collect();
+        self.chars += expected.len();
+        self.correct_chars += correct.iter().filter(|correct| **correct).count();
+        self.sentences += 1;
+        if result == expected {
+            self.correct_sentences += 1;
+        }
+        if let Some(syllables) = syllables {
+            for (syllable, correct) in syllables.iter().zip(&correct) {
+                let errors = self.syllables.entry(syllable.to_string()).or_default();
+                errors.total += 1;
+                if !correct {
+                    errors.wrong += 1;
+                }
+            }
+        }
+    }
+
+    /// Fraction of the expected chars decoded correctly
+    pub fn char_accuracy(&self) -> f64 {
+        self.correct_chars as f64 / self.chars.max(1) as f64
+    }
+
+    /// Fraction of the sentences decoded without any error
+    pub fn sentence_accuracy(&self) -> f64 {
+        self.correct_sentences as f64 / self.sentences.max(1) as f64
+    }
+
+    /// At most `n` syllables with the most wrong chars, ties broken by the
+    /// higher error rate
+    pub fn worst_syllables(&self, n: usize) -> Vec<(&str, SyllableErrors)> {
+        let mut res: Vec<(&str, SyllableErrors)> = self
+            .syllables
+            .iter()
+            .filter(|(_, errors)| errors.wrong > 0)
+            .map(|(syllable, errors)| (syllable.as_str(), *errors))
+            .collect();
+        res.sort_by(|a, b| {
+            b.1.wrong
+                .cmp(&a.1.wrong)
+                .then(b.1.error_rate().partial_cmp(&a.1.error_rate()).unwrap())
+        });
+        res.truncate(n);
+        res
+    }
+}
+
+/// A segmentation of `graph` into exactly `count` syllables, if any
+fn segmentation_of_len(graph: &SyllableGraph, count: usize) -> Option<Vec<&str>> {
+    // next[pos][k] is an edge starting at `pos` on a path of `k` syllables to the end
+    let mut next: Vec<HashMap<usize, usize>> = vec![HashMap::new(); graph.len + 1];
+    next[graph.len].insert(0, usize::MAX);
+    for start in (0..graph.len).rev() {
+        for (index, edge) in graph.edges.iter().enumerate() {
+            if edge.start != start {
+                continue;
+            }
+            let lens: Vec<usize> = next[edge.end].keys().map(|len| len + 1).collect();
+            for len in lens {
+                next[start].entry(len).or_insert(index);
+            }
+        }
+    }
+    let mut res = Vec::with_capacity(count);
+    let mut pos = 0;
+    while res.len() < count {
+        let edge = &graph.edges[*next[pos].get(&(count - res.len()))?];
+        res.push(edge.syllable.as_str());
+        pos = edge.end;
+    }
+    Some(res)
+}
+
+/// Decode every line of `input` with `model` and compare the best sentence
+/// to the line of `expected`
+///
+/// Errors by syllable are only counted for sentences whose tokens are all
+/// pinyin, as far as they can be split into as many syllables as there
+/// are expected chars.
+pub fn evaluate(
+    model: &Smoothed,
+    input: &[String],
+    expected: &[String],
+    policy: UnknownPolicy,
+) -> Evaluation {
+    let segmenter = Segmenter::new(model.spellings());
+    let mut res = Evaluation::default();
+    for (input, expected) in input.iter().zip(expected) {
+        let pieces = segmenter.pieces(input);
+        let result = model
+            .convert_pieces(&pieces, 1, policy)
+            .ok()
+            .and_then(|results| results.into_iter().next())
+            .map(|(result, _)| result);
+        if result.is_none() {
+            res.failed_sentences += 1;
+        }
+        let syllables = match pieces.as_slice() {
+            [Piece::Syllables(graph)] => segmentation_of_len(graph, expected.chars().count()),
+            _ => None,
+        };
+        res.add(
+            syllables.as_deref(),
+            result.as_deref().unwrap_or(""),
+            expected,
+        );
+    }
+    res
+}
+
+/// Lines of a test set file, trimmed
+pub fn read_lines<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Error> {
+    let mut lines = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        lines.push(line?.trim().to_string());
+    }
+    Ok(lines)
+}
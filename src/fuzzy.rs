@@ -0,0 +1,296 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Weight of a fuzzy alternative unless given, an exact match has weight 1
+pub const DEFAULT_FUZZY_WEIGHT: f64 = 0.1;
+
+/// Initials and finals commonly confused, the initials first
+const FUZZY_PAIRS: [(&str, &str); 8] = [
+    ("z", "zh"),
+    ("c", "ch"),
+    ("s", "sh"),
+    ("n", "l"),
+    ("f", "h"),
+    ("an", "ang"),
+    ("en", "eng"),
+    ("in", "ing"),
+];
+
+/// Number of initial pairs at the start of `FUZZY_PAIRS`
+const FUZZY_INITIALS: usize = 5;
+
+/// Two spellings of an initial or a final taken for each other
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyRule {
+    pub a: String,
+    pub b: String,
+    /// Whether `a` and `b` are initials, finals otherwise
+    pub initial: bool,
+    /// Probability of typing one for the other, from 0 to 1
+    pub weight: f64,
+}
+
+impl FuzzyRule {
+    /// `syllable` with `a` and `b` swapped, if it starts or ends with one
+    fn apply(&self, syllable: &str) -> Option<String> {
+        // the longer spelling first, "zh" starts with "z"
+        let (short, long) = if self.a.len() < self.b.len() {
+            (&self.a, &self.b)
+        } else {
+            (&self.b, &self.a)
+        };
+        if self.initial {
+            if let Some(rest) = syllable.strip_prefix(long.as_str()) {
+                return Some(format!("{}{}", short, rest));
+            }
+            let rest = syllable.strip_prefix(short.as_str())?;
+            Some(format!("{}{}", long, rest))
+        } else {
+            if let Some(rest) = syllable.strip_suffix(long.as_str()) {
+                return Some(format!("{}{}", rest, short));
+            }
+            let rest = syllable.strip_suffix(short.as_str())?;
+            Some(format!("{}{}", rest, long))
+        }
+    }
+}
+
+impl FromStr for FuzzyRule {
+    type Err = String;
+
+    /// A pair like "z/zh" or "an/ang:0.3", in any order
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pair, weight) = match s.find(':') {
+            Some(pos) => (
+                &s[..pos],
+                s[pos + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid fuzzy weight in {:?}", s))?,
+            ),
+            None => (s, DEFAULT_FUZZY_WEIGHT),
+        };
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(format!("fuzzy weight of {:?} is not between 0 and 1", s));
+        }
+        let mut spellings = pair.split('/');
+        let (a, b) = match (spellings.next(), spellings.next(), spellings.next()) {
+            (Some(a), Some(b), None) => (a.trim(), b.trim()),
+            _ => return Err(format!("expected a pair like \"z/zh\", got {:?}", s)),
+        };
+        let index = FUZZY_PAIRS
+            .iter()
+            .position(|pair| *pair == (a, b) || *pair == (b, a))
+            .ok_or_else(|| format!("unknown fuzzy pair {:?}", pair))?;
+        Ok(FuzzyRule {
+            a: a.to_string(),
+            b: b.to_string(),
+            initial: index < FUZZY_INITIALS,
+            weight,
+        })
+    }
+}
+
+/// Confusions to tolerate in the input, every syllable also stands for its
+/// alternatives under these rules, scored with their weights
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FuzzyPinyin {
+    pub rules: Vec<FuzzyRule>,
+}
+
+impl FuzzyPinyin {
+    /// Every known confusion with `weight`
+    pub fn all(weight: f64) -> Self {
+        FuzzyPinyin {
+            rules: FUZZY_PAIRS
+                .iter()
+                .enumerate()
+                .map(|(index, (a, b))| FuzzyRule {
+                    a: a.to_string(),
+                    b: b.to_string(),
+                    initial: index < FUZZY_INITIALS,
+                    weight,
+                })
+                .collect(),
+        }
+    }
+
+    /// `syllable` and its alternatives with their log weights, `syllable`
+    /// first with 0, at most one initial and one final are swapped
+    ///
+    /// A trailing tone number is kept.
+    pub fn expand(&self, syllable: &str) -> Vec<(String, f64)> {
+        let (base, tone) = match syllable.char_indices().last() {
+            Some((pos, ch)) if ch.is_ascii_digit() => syllable.split_at(pos),
+            _ => (syllable, ""),
+        };
+        let mut initials = vec![(base.to_string(), 0.0)];
+        for rule in self.rules.iter().filter(|rule| rule.initial) {
+            if let Some(alternative) = rule.apply(base) {
+                initials.push((alternative, rule.weight.ln()));
+            }
+        }
+        let mut res: Vec<(String, f64)> = Vec::new();
+        for (spelling, log_weight) in initials {
+            let mut finals = vec![(spelling.clone(), log_weight)];
+            for rule in self.rules.iter().filter(|rule| !rule.initial) {
+                if let Some(alternative) = rule.apply(&spelling) {
+                    finals.push((alternative, log_weight + rule.weight.ln()));
+                }
+            }
+            for (spelling, log_weight) in finals {
+                let spelling = format!("{}{}", spelling, tone);
+                match res.iter_mut().find(|(known, _)| *known == spelling) {
+                    Some(known) => known.1 = known.1.max(log_weight),
+                    None => res.push((spelling, log_weight)),
+                }
+            }
+        }
+        res
+    }
+
+    /// Every spelling that stands for at least one of `syllables`
+    pub fn spellings<'a, I>(&self, syllables: I) -> BTreeSet<String>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        // the rules are symmetric, so the alternatives of a syllable are
+        // the spellings that stand for it
+        syllables
+            .into_iter()
+            .flat_map(|syllable| self.expand(syllable))
+            .map(|(spelling, _)| spelling)
+            .collect()
+    }
+
+    /// `graph` with every edge replaced by its alternatives in `mapping`,
+    /// an edge without any is kept as is
+    pub fn expand_graph(
+        &self,
+        graph: &SyllableGraph,
+        mapping: &BTreeMap<String, Vec<char>>,
+    ) -> SyllableGraph {
+        let mut res = SyllableGraph {
+            len: graph.len,
+            edges: Vec::with_capacity(graph.edges.len()),
+        };
+        for edge in &graph.edges {
+            let alternatives: Vec<Edge> = self
+                .expand(&edge.syllable)
+                .into_iter()
+                .filter(|(syllable, _)| mapping.contains_key(syllable))
+                .map(|(syllable, log_weight)| Edge {
+                    start: edge.start,
+                    end: edge.end,
+                    syllable,
+                    log_weight: edge.log_weight + log_weight,
+                })
+                .collect();
+            if alternatives.is_empty() {
+                res.edges.push(edge.clone());
+            } else {
+                res.edges.extend(alternatives);
+            }
+        }
+        res
+    }
+}
+
+impl FromStr for FuzzyPinyin {
+    type Err = String;
+
+    /// "all" or "all:weight" for every confusion, otherwise comma separated
+    /// pairs like "z/zh,n/l:0.3"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(FuzzyPinyin::all(DEFAULT_FUZZY_WEIGHT));
+        }
+        if let Some(weight) = s.strip_prefix("all:") {
+            let weight: f64 = weight
+                .parse()
+                .map_err(|_| format!("invalid fuzzy weight in {:?}", s))?;
+            if !(0.0..=1.0).contains(&weight) {
+                return Err(format!("fuzzy weight of {:?} is not between 0 and 1", s));
+            }
+            return Ok(FuzzyPinyin::all(weight));
+        }
+        Ok(FuzzyPinyin {
+            rules: s.split(',').map(str::parse).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut expanded: Vec<(String, f64)>) -> Vec<(String, f64)> {
+        expanded.sort_by(|a, b| a.0.cmp(&b.0));
+        expanded
+    }
+
+    #[test]
+    fn expand_weights() {
+        let fuzzy: FuzzyPinyin = "z/zh:0.3,an/ang:0.2".parse().unwrap();
+        let expanded = fuzzy.expand("zhang");
+        assert_eq!(expanded[0], ("zhang".to_string(), 0.0));
+        let expected = vec![
+            ("zan".to_string(), 0.3f64.ln() + 0.2f64.ln()),
+            ("zang".to_string(), 0.3f64.ln()),
+            ("zhan".to_string(), 0.2f64.ln()),
+            ("zhang".to_string(), 0.0),
+        ];
+        assert_eq!(sorted(expanded), expected);
+
+        // the tone number stays at the end
+        let toned: Vec<String> = fuzzy
+            .expand("zhang1")
+            .into_iter()
+            .map(|(spelling, _)| spelling)
+            .collect();
+        assert_eq!(toned, vec!["zhang1", "zhan1", "zang1", "zan1"]);
+    }
+
+    #[test]
+    fn expand_is_symmetric() {
+        let fuzzy = FuzzyPinyin::all(0.1);
+        for syllable in &["zhang", "san", "lin", "feng", "cheng", "hui", "nv"] {
+            for (alternative, log_weight) in fuzzy.expand(syllable) {
+                let back = fuzzy.expand(&alternative);
+                let found = back.iter().find(|(spelling, _)| spelling == syllable);
+                assert_eq!(
+                    found.map(|(_, back_weight)| *back_weight),
+                    Some(log_weight),
+                    "{} -> {}",
+                    syllable,
+                    alternative
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exact_matches_outrank_alternatives() {
+        let mapping: BTreeMap<String, Vec<char>> = vec![
+            ("zhang".to_string(), vec!['张']),
+            ("zang".to_string(), vec!['脏']),
+        ]
+        .into_iter()
+        .collect();
+        let fuzzy = FuzzyPinyin::all(0.1);
+        let graph = fuzzy.expand_graph(&SyllableGraph::from_words(&["zhang"]), &mapping);
+        assert_eq!(graph.edges.len(), 2);
+
+        let decode = |zhang: f64, zang: f64| {
+            let mut model = Model::<Match1>::from_mapping(mapping.clone()).unwrap();
+            model.insert_prob(&Match1::from_str("张"), zhang).unwrap();
+            model.insert_prob(&Match1::from_str("脏"), zang).unwrap();
+            let mut lattice = Lattice::default();
+            lattice.extend(&model, &graph, 1).unwrap();
+            lattice.best().unwrap().0
+        };
+        assert_eq!(decode(0.5, 0.5), "张");
+        // the alternative only wins if its char is more than 10 times as likely
+        assert_eq!(decode(0.05, 0.95), "脏");
+    }
+}
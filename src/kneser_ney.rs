@@ -0,0 +1,189 @@
+use super::*;
+use std::collections::BTreeSet;
+
+/// Probabilities and backoff weights of all n-grams of one order
+#[derive(Debug, Default)]
+pub struct Estimate {
+    /// `P(last char | prefix)`, already interpolated with the lower orders
+    pub prob: BTreeMap<Vec<char>, f64>,
+    /// Weight of the lower order distribution when this n-gram is the
+    /// context of an unseen longer one
+    pub backoff: BTreeMap<Vec<char>, f64>,
+}
+
+/// Modified Kneser-Ney discounts for counts of 1, 2 and 3 or more,
+/// estimated from the count-of-counts
+fn discounts<'a, I: Iterator<Item = &'a u32>>(counts: I) -> [f64; 3] {
+    let mut n = [0u64; 4];
+    for count in counts {
+        if *count >= 1 && *count <= 4 {
+            n[*count as usize - 1] += 1;
+        }
+    }
+    let [n1, n2, n3, n4] = n.map(|n| n as f64);
+    let y = n1 / (n1 + 2.0 * n2);
+    let d = [
+        1.0 - 2.0 * y * n2 / n1,
+        2.0 - 3.0 * y * n3 / n2,
+        3.0 - 4.0 * y * n4 / n3,
+    ];
+    // too few distinct counts in a small corpus, fall back to a fixed discount
+    let mut res = [0.5, 1.0, 1.5];
+    for (i, d) in d.iter().enumerate() {
+        if d.is_finite() && *d > 0.0 && *d < (i + 1) as f64 {
+            res[i] = *d;
+        }
+    }
+    res
+}
+
+/// Interpolated modified Kneser-Ney estimation
+///
+/// `occur[n - 1]` holds the raw counts of all n-grams. The highest order
+/// uses them directly, lower orders use continuation counts, i.e. the
+/// number of distinct chars seen before an n-gram. Unigrams interpolate
+/// with the uniform distribution over `vocab`.
+pub fn kneser_ney(occur: &[BTreeMap<Vec<char>, u32>], vocab: &BTreeSet<char>) -> Vec<Estimate> {
+    let max_order = occur.len();
+    let mut res: Vec<Estimate> = Vec::with_capacity(max_order);
+    for order in 1..=max_order {
+        let counts: BTreeMap<&[char], u32> = if order == max_order {
+            occur[order - 1]
+                .iter()
+                .map(|(ngram, count)| (ngram.as_slice(), *count))
+                .collect()
+        } else {
+            let mut counts: BTreeMap<&[char], u32> = occur[order - 1]
+                .keys()
+                .map(|ngram| (ngram.as_slice(), 0))
+                .collect();
+            for ngram in occur[order].keys() {
+                *counts.get_mut(&ngram[1..]).expect("suffix counted") += 1;
+            }
+            counts
+        };
+        let d = discounts(counts.values());
+
+        // total count and number of n-grams with count 1, 2 and 3+ per context
+        let mut contexts: BTreeMap<&[char], (u32, [u32; 3])> = BTreeMap::new();
+        for (ngram, count) in &counts {
+            let entry = contexts.entry(&ngram[..order - 1]).or_default();
+            entry.0 += count;
+            if *count > 0 {
+                entry.1[(*count as usize).min(3) - 1] += 1;
+            }
+        }
+        let gamma = |context: &[char]| -> f64 {
+            match contexts.get(context) {
+                Some((total, n)) if *total > 0 => {
+                    (d[0] * n[0] as f64 + d[1] * n[1] as f64 + d[2] * n[2] as f64) / *total as f64
+                }
+                _ => 1.0,
+            }
+        };
+
+        let mut estimate = Estimate::default();
+        let uniform = 1.0 / vocab.len() as f64;
+        let lower = |ngram: &[char]| -> f64 {
+            match res.last() {
+                Some(lower) => lower.prob[&ngram[1..]],
+                None => uniform,
+            }
+        };
+        for (ngram, count) in &counts {
+            let context = &ngram[..order - 1];
+            let (total, _) = contexts[context];
+            let discounted = if *count == 0 {
+                0.0
+            } else {
+                (*count as f64 - d[(*count as usize).min(3) - 1]).max(0.0) / total as f64
+            };
+            let prob = discounted + gamma(context) * lower(ngram);
+            estimate.prob.insert(ngram.to_vec(), prob);
+        }
+        if order == 1 {
+            // chars never seen in the corpus still get the uniform share
+            for ch in vocab {
+                estimate
+                    .prob
+                    .entry(vec![*ch])
+                    .or_insert_with(|| gamma(&[]) * uniform);
+            }
+        }
+        if let Some(lower) = res.last_mut() {
+            for context in contexts.keys() {
+                lower.backoff.insert(context.to_vec(), gamma(context));
+            }
+        }
+        res.push(estimate);
+    }
+    res
+}
+
+impl<T: Match> Model<T> {
+    /// Fill in the probabilities and backoff weights estimated for order `T::order()`
+    pub fn insert_estimate(&mut self, estimate: &Estimate) -> Result<(), Error> {
+        for (ngram, prob) in &estimate.prob {
+            self.insert_prob(&T::from_chars(ngram), *prob)?;
+        }
+        for (ngram, weight) in &estimate.backoff {
+            self.insert_backoff(&T::from_chars(ngram), *weight)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    /// Counts of the n-grams of `texts` of orders 1 to 3
+    fn occur(texts: &[&str]) -> Vec<BTreeMap<Vec<char>, u32>> {
+        let mut res = vec![BTreeMap::new(); 3];
+        for text in texts {
+            let chars: Vec<char> = text.chars().collect();
+            for (order, counts) in res.iter_mut().enumerate() {
+                for ngram in chars.windows(order + 1) {
+                    *counts.entry(ngram.to_vec()).or_insert(0) += 1;
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn discounts_stay_below_their_counts() {
+        for d in &[discounts([1, 1, 1, 2, 3, 4].iter()), discounts([].iter())] {
+            for (i, d) in d.iter().enumerate() {
+                assert!(*d > 0.0 && *d < (i + 1) as f64, "{:?}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn estimates_are_normalized() {
+        let vocab: BTreeSet<char> = "abcdx".chars().collect();
+        let occur = occur(&["abcab", "abdab", "bcabd", "cabca", "dcba"]);
+        let estimates = kneser_ney(&occur, &vocab);
+        let mapping = mapping_of(&vocab.iter().collect::<String>());
+        let mut model1 = Model::<Match1>::from_mapping(mapping.clone()).unwrap();
+        let mut model2 = Model::<Match2>::from_mapping(mapping.clone()).unwrap();
+        let mut model3 = Model::<Match3>::from_mapping(mapping).unwrap();
+        model1.insert_estimate(&estimates[0]).unwrap();
+        model2.insert_estimate(&estimates[1]).unwrap();
+        model3.insert_estimate(&estimates[2]).unwrap();
+        let models: [&dyn LanguageModel; 3] = [&model1, &model2, &model3];
+
+        let mut histories: Vec<Vec<char>> = vec![Vec::new()];
+        histories.extend(occur[0].keys().cloned());
+        histories.extend(occur[1].keys().cloned());
+        for history in &histories {
+            let total: f64 = vocab
+                .iter()
+                .map(|ch| backoff_prob(&models, history, *ch))
+                .sum();
+            assert!((total - 1.0).abs() < 1e-9, "{:?}: {}", history, total);
+        }
+    }
+}
@@ -1,120 +1,422 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::fmt::Debug;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::rc::Rc;
 
-mod match1;
-pub use match1::*;
+mod binary;
+pub use binary::*;
 
-mod match2;
-pub use match2::*;
+mod clean;
+pub use clean::*;
 
-mod match3;
-pub use match3::*;
+mod error;
+pub use error::*;
 
-pub trait Match: Ord + Debug {
-    type Prefix: Ord + Debug + Match;
+mod eval;
+pub use eval::*;
 
-    fn new(prefix: &Self::Prefix, end: char) -> Self;
+mod corpus;
+pub use corpus::*;
 
-    fn min_len() -> usize;
+mod counts;
+pub use counts::*;
 
-    fn get_prefix(input: &[Vec<char>]) -> Vec<Self::Prefix>;
+mod fuzzy;
+pub use fuzzy::*;
 
-    fn shift_prefix(&self) -> Self::Prefix;
-    
-    fn empty() -> Self;
+mod kneser_ney;
+pub use kneser_ney::*;
+
+mod mapping;
+pub use mapping::*;
+
+mod merge;
+pub use merge::*;
+
+mod metadata;
+pub use metadata::*;
+
+mod model_dir;
+pub use model_dir::*;
+
+mod ngram;
+pub use ngram::*;
+
+mod observer;
+pub use observer::*;
+
+mod perplexity;
+pub use perplexity::*;
+
+mod prune;
+pub use prune::*;
+
+mod segment;
+pub use segment::*;
+
+mod smoothing;
+pub use smoothing::*;
+
+mod spill;
+pub use spill::*;
+
+#[cfg(test)]
+mod test_util;
+
+mod tone;
+pub use tone::*;
+
+mod viterbi;
+pub use viterbi::*;
+
+mod vocab;
+pub use vocab::*;
+
+pub trait Match: Ord + Debug + Display + Clone {
+    /// Number of chars in a match
+    fn order() -> usize;
+
+    /// Build a match from the last `order() - 1` chars of `history` and `end`
+    fn new(history: &[char], end: char) -> Self;
+
+    fn chars(&self) -> &[char];
+
+    /// Build a match from exactly `order()` chars
+    fn from_chars(chars: &[char]) -> Self {
+        let (end, history) = chars.split_last().expect("empty n-gram");
+        Self::new(history, *end)
+    }
+
+    /// All chars but the last one, i.e. the context of this match
+    fn get_prefix(&self) -> &[char] {
+        &self.chars()[..Self::order() - 1]
+    }
+
+    /// All chars but the first one, i.e. the context of the next match
+    fn shift_prefix(&self) -> &[char] {
+        &self.chars()[1..]
+    }
 
     fn from_str(s: &str) -> Self;
 }
 
+/// Anything that scores a chinese character given the preceding ones
+pub trait LanguageModel {
+    /// Maximum number of chars in an n-gram, the decoder keeps `order() - 1`
+    /// chars of history per state
+    fn order(&self) -> usize;
+
+    /// Pinyin syllables and the chinese characters that can be read as them
+    fn mapping(&self) -> &BTreeMap<String, Vec<char>>;
+
+    /// Chinese characters that can be read as `syllable`
+    fn candidates(&self, syllable: &str) -> Option<&[char]> {
+        self.mapping().get(syllable).map(|chars| chars.as_slice())
+    }
+
+    /// Log probability of `ch` following `history`, `None` if unseen
+    ///
+    /// `history` holds at most `order() - 1` chars, fewer at the beginning of
+    /// a sentence.
+    fn log_prob(&self, history: &[char], ch: char) -> Option<f64>;
+
+    /// Log weight of the lower order distribution after `context`, which
+    /// holds exactly `order()` chars, if the model stores one
+    fn backoff(&self, _context: &[char]) -> Option<f64> {
+        None
+    }
+
+    /// Description of the model file, if loaded from one
+    fn metadata(&self) -> Option<&Metadata> {
+        None
+    }
+}
+
+/// Share a model, e.g. between a `Smoothed` and other users
+impl<L: LanguageModel + ?Sized> LanguageModel for Rc<L> {
+    fn order(&self) -> usize {
+        (**self).order()
+    }
+
+    fn mapping(&self) -> &BTreeMap<String, Vec<char>> {
+        (**self).mapping()
+    }
+
+    fn candidates(&self, syllable: &str) -> Option<&[char]> {
+        (**self).candidates(syllable)
+    }
+
+    fn log_prob(&self, history: &[char], ch: char) -> Option<f64> {
+        (**self).log_prob(history, ch)
+    }
+
+    fn backoff(&self, context: &[char]) -> Option<f64> {
+        (**self).backoff(context)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        (**self).metadata()
+    }
+}
+
+/// N-gram probabilities of order `T::order()`, keyed by the packed ids of
+/// their chars in `vocab()`
 #[derive(Debug)]
 pub struct Model<T: Match> {
+    /// Written to the model file, `order` and `vocab_size` are filled in on save
+    pub metadata: Metadata,
     pub mapping: BTreeMap<String, Vec<char>>,
-    pub prob: BTreeMap<T, f64>,
+    vocab: Vocab,
+    prob: HashMap<u64, f64>,
+    /// Backoff weights of n-grams as contexts of the next order,
+    /// empty unless trained with Kneser-Ney smoothing
+    backoff: HashMap<u64, f64>,
+    order: PhantomData<T>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonModel {
+    /// Missing in files written before metadata was introduced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    /// CRC-32 of everything else, see `JsonModel::update_checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
     pub mapping: BTreeMap<String, Vec<char>>,
     pub prob: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub backoff: BTreeMap<String, f64>,
+}
+
+impl JsonModel {
+    /// Load a gzipped JSON model, failing if its checksum does not match
+    pub fn load_from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        let json_model: JsonModel = serde_json::from_reader(GzDecoder::new(reader))?;
+        if let Some(checksum) = json_model.checksum {
+            if checksum != json_model.compute_checksum()? {
+                return Err(Error::InvalidModel(
+                    "checksum mismatch, the file is corrupted".to_string(),
+                ));
+            }
+        }
+        Ok(json_model)
+    }
+
+    fn compute_checksum(&self) -> Result<u32, Error> {
+        let content = (&self.metadata, &self.mapping, &self.prob, &self.backoff);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&serde_json::to_vec(&content)?);
+        Ok(hasher.finalize())
+    }
+
+    /// Recompute the checksum, needed after changing the model
+    pub fn update_checksum(&mut self) -> Result<(), Error> {
+        self.checksum = Some(self.compute_checksum()?);
+        Ok(())
+    }
+
+    /// Check that this can be loaded as a model of `order`: the metadata,
+    /// if any, must agree and every n-gram must have `order` chars
+    pub fn validate(&self, order: usize) -> Result<(), Error> {
+        if let Some(metadata) = &self.metadata {
+            metadata.validate(order)?;
+        }
+        for key in self.prob.keys().chain(self.backoff.keys()) {
+            let len = key.chars().count();
+            if len != order {
+                return Err(Error::InvalidModel(format!(
+                    "n-gram {:?} of {} chars in a model of order {}",
+                    key, len, order
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Save as gzipped JSON, the checksum is written as is
+    pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut writer = GzEncoder::new(writer, Compression::default());
+        serde_json::to_writer(&mut writer, self)?;
+        writer.finish()?.flush()?;
+        Ok(())
+    }
 }
 
 impl<T: Match> Model<T> {
     pub fn empty() -> Self {
         Model {
+            metadata: Metadata::new(T::order()),
             mapping: BTreeMap::new(),
-            prob: BTreeMap::new(),
+            vocab: Vocab::new(),
+            prob: HashMap::new(),
+            backoff: HashMap::new(),
+            order: PhantomData,
         }
     }
 
-    /// Convert a pinyin sentence to chinese
-    pub fn convert(
-        &self,
-        words: &Vec<&str>,
-        last_model: Option<
-            BTreeMap<<<T as Match>::Prefix as Match>::Prefix, (f64, f64, Vec<char>)>,
-        >,
-    ) -> (String, BTreeMap<T::Prefix, (f64, f64, Vec<char>)>) {
-        let min_len = T::min_len();
-        assert!(words.len() >= min_len);
-
-        let mut cur_prefixes_prob: BTreeMap<T::Prefix, (f64, f64, Vec<char>)> = BTreeMap::new();
-        if let Some(last) = last_model {
-            println!("last {:?}", last);
-            let current = words[min_len-1];
-            let chars = self.mapping.get(current).expect("found pinyin");
-            for ch in chars {
-                for (_prefix, (old_prob, _sum_prob, path)) in last.iter() {
-                    //let new_prefix = T::Prefix::new(&prefix, path[path.len()-1]);
-                    let s: String = path.iter().collect();
-                    let new_prefix = T::Prefix::from_str(&s);
-                    let new_match = T::new(&new_prefix, *ch);
-                    println!("{:?}", new_match);
-                    if let Some(prob) = self.prob.get(&new_match) {
-                        cur_prefixes_prob.insert(new_prefix, (*old_prob * prob, 0.0, path.clone()));
-                    }
-                }
+    /// A model without n-grams, the chars of `mapping` get the lowest ids
+    pub fn from_mapping(mapping: BTreeMap<String, Vec<char>>) -> Result<Self, Error> {
+        Ok(Model {
+            vocab: Vocab::from_mapping(&mapping)?,
+            mapping,
+            ..Self::empty()
+        })
+    }
+
+    pub fn vocab(&self) -> &Vocab {
+        &self.vocab
+    }
+
+    /// Number of n-grams with a probability
+    pub fn len(&self) -> usize {
+        self.prob.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prob.is_empty()
+    }
+
+    /// `P(last char | prefix)` of `ngram`
+    pub fn get_prob(&self, ngram: &T) -> Option<f64> {
+        let key = self.vocab.key(ngram.chars().iter().copied())?;
+        self.prob.get(&key).copied()
+    }
+
+    /// Fails if `ngram` does not fit into a packed key
+    pub fn insert_prob(&mut self, ngram: &T, prob: f64) -> Result<(), Error> {
+        let key = self.vocab.intern_key(ngram.chars())?;
+        self.prob.insert(key, prob);
+        Ok(())
+    }
+
+    /// Remove the probability of `ngram`, returning it if it was stored
+    pub fn remove_prob(&mut self, ngram: &T) -> Option<f64> {
+        let key = self.vocab.key(ngram.chars().iter().copied())?;
+        self.prob.remove(&key)
+    }
+
+    /// Backoff weight of `ngram` as the context of the next order
+    pub fn get_backoff(&self, ngram: &T) -> Option<f64> {
+        let key = self.vocab.key(ngram.chars().iter().copied())?;
+        self.backoff.get(&key).copied()
+    }
+
+    /// Fails if `ngram` does not fit into a packed key
+    pub fn insert_backoff(&mut self, ngram: &T, weight: f64) -> Result<(), Error> {
+        let key = self.vocab.intern_key(ngram.chars())?;
+        self.backoff.insert(key, weight);
+        Ok(())
+    }
+
+    /// All n-grams and their probabilities, in no particular order
+    pub fn probs(&self) -> impl Iterator<Item = (T, f64)> + '_ {
+        self.prob
+            .iter()
+            .map(move |(key, prob)| (T::from_chars(&self.vocab.unpack(*key, T::order())), *prob))
+    }
+
+    /// All n-grams and their backoff weights, in no particular order
+    pub fn backoffs(&self) -> impl Iterator<Item = (T, f64)> + '_ {
+        self.backoff.iter().map(move |(key, weight)| {
+            (T::from_chars(&self.vocab.unpack(*key, T::order())), *weight)
+        })
+    }
+
+    /// Default file name of a model of this order, e.g. `model3.json.gz`
+    pub fn file_name() -> String {
+        format!("model{}.json.gz", T::order())
+    }
+
+    /// Load the model embedded into the library at compile time, only
+    /// orders 1 to 3 are bundled
+    #[cfg(feature = "bundled")]
+    pub fn load() -> Result<Self, Error> {
+        let data: &[u8] = match T::order() {
+            1 => include_bytes!("model1.json.gz"),
+            2 => include_bytes!("model2.json.gz"),
+            3 => include_bytes!("model3.json.gz"),
+            order => {
+                return Err(Error::InvalidModel(format!(
+                    "no bundled model of order {}",
+                    order
+                )))
             }
-        } else {
-            cur_prefixes_prob.insert(T::Prefix::empty(), (1.0, 0.0, Vec::new()));
+        };
+        Self::load_from_reader(data)
+    }
+
+    /// Load a gzipped JSON model from a file
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::load_from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load a gzipped JSON model
+    pub fn load_from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::from_json(JsonModel::load_from_reader(reader)?)
+    }
+
+    /// Fails if `json_model` is not of order `T::order()`
+    pub fn from_json(json_model: JsonModel) -> Result<Self, Error> {
+        json_model.validate(T::order())?;
+        let mut model = Self::from_mapping(json_model.mapping)?;
+        model.metadata = json_model.metadata.unwrap_or(Metadata {
+            created: 0,
+            ..Metadata::new(T::order())
+        });
+        for (key, value) in &json_model.prob {
+            model.insert_prob(&T::from_str(key), *value)?;
         }
-        println!("{:?}", cur_prefixes_prob);
-        for i in (min_len - 1)..words.len() {
-            let current = words[i];
-            let chars = self.mapping.get(current).expect("found pinyin");
-            let mut new_prefixes_prob = BTreeMap::new();
-            for (cur_prefix, (prob_prefix, _, path)) in cur_prefixes_prob.iter() {
-                for ch in chars {
-                    let new_match = T::new(cur_prefix, *ch);
-                    if let Some(prob) = self.prob.get(&new_match) {
-                        let entry = new_prefixes_prob
-                            .entry(new_match.shift_prefix())
-                            .or_insert_with(|| {
-                                let mut new_path = path.clone();
-                                new_path.push(' ');
-                                (0.0, 0.0, new_path)
-                            });
-                        entry.0 += prob_prefix * prob;
-                        if prob_prefix * prob > entry.1 {
-                            entry.1 = prob_prefix * prob;
-                            *entry.2.last_mut().unwrap() = *ch;
-                        }
-                    }
-                }
-            }
-            cur_prefixes_prob = new_prefixes_prob;
-            println!("{:?}", cur_prefixes_prob);
+        for (key, value) in &json_model.backoff {
+            model.insert_backoff(&T::from_str(key), *value)?;
         }
+        Ok(model)
+    }
 
-        let mut ans = Vec::new();
-        let mut max_prob = 0.0;
-        for (_, (prob, _, path)) in &cur_prefixes_prob {
-            if *prob > max_prob {
-                max_prob = *prob;
-                ans = path.clone();
-            }
-        }
-        ans.push('\n');
-        (ans.into_iter().collect(), cur_prefixes_prob)
+    /// Save to `file_name()` in the current directory
+    pub fn save(&self) -> Result<(), Error> {
+        self.save_to(Self::file_name())
+    }
+
+    /// Save as gzipped JSON to a file
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.save_to_writer(BufWriter::new(File::create(path)?))
+    }
+
+    /// Save as gzipped JSON
+    pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        self.to_json()?.save_to_writer(writer)
+    }
+
+    pub fn to_json(&self) -> Result<JsonModel, Error> {
+        let prob = self
+            .probs()
+            .map(|(ngram, prob)| (ngram.to_string(), prob))
+            .collect();
+        let backoff = self
+            .backoffs()
+            .map(|(ngram, weight)| (ngram.to_string(), weight))
+            .collect();
+
+        let mut json_model = JsonModel {
+            metadata: Some(Metadata {
+                version: MODEL_VERSION,
+                order: T::order(),
+                vocab_size: self.vocab.len(),
+                ..self.metadata.clone()
+            }),
+            checksum: None,
+            mapping: self.mapping.clone(),
+            prob,
+            backoff,
+        };
+        json_model.update_checksum()?;
+        Ok(json_model)
     }
 }
@@ -0,0 +1,359 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Every toneless pinyin syllable, ü written as v
+const SYLLABLES: &str = "
+    a ai an ang ao
+    ba bai ban bang bao bei ben beng bi bian biao bie bin bing bo bu
+    ca cai can cang cao ce cei cen ceng ci cong cou cu cuan cui cun cuo
+    cha chai chan chang chao che chen cheng chi chong chou chu chua chuai chuan chuang chui chun chuo
+    da dai dan dang dao de dei den deng di dia dian diao die ding diu dong dou du duan dui dun duo
+    e ei en eng er
+    fa fan fang fei fen feng fiao fo fou fu
+    ga gai gan gang gao ge gei gen geng gong gou gu gua guai guan guang gui gun guo
+    ha hai han hang hao he hei hen heng hm hng hong hou hu hua huai huan huang hui hun huo
+    ji jia jian jiang jiao jie jin jing jiong jiu ju juan jue jun
+    ka kai kan kang kao ke kei ken keng kong kou ku kua kuai kuan kuang kui kun kuo
+    la lai lan lang lao le lei leng li lia lian liang liao lie lin ling liu lo long lou lu luan lue lun luo lv lve
+    m ma mai man mang mao me mei men meng mi mian miao mie min ming miu mo mou mu
+    n na nai nan nang nao ne nei nen neng ng ni nian niang niao nie nin ning niu nong nou nu nuan nue nun nuo nv nve
+    o ou
+    pa pai pan pang pao pei pen peng pi pian piao pie pin ping po pou pu
+    qi qia qian qiang qiao qie qin qing qiong qiu qu quan que qun
+    ra ran rang rao re ren reng ri rong rou ru rua ruan rui run ruo
+    sa sai san sang sao se sen seng si song sou su suan sui sun suo
+    sha shai shan shang shao she shei shen sheng shi shou shu shua shuai shuan shuang shui shun shuo
+    ta tai tan tang tao te tei teng ti tian tiao tie ting tong tou tu tuan tui tun tuo
+    wa wai wan wang wei wen weng wo wu
+    xi xia xian xiang xiao xie xin xing xiong xiu xu xuan xue xun
+    ya yan yang yao ye yi yin ying yo yong you yu yuan yue yun
+    za zai zan zang zao ze zei zen zeng zi zong zou zu zuan zui zun zuo
+    zha zhai zhan zhang zhao zhe zhei zhen zheng zhi zhong zhou zhu zhua zhuai zhuan zhuang zhui zhun zhuo
+";
+
+/// Whether `syllable` is a toneless pinyin syllable in lowercase, ü written as v
+pub fn is_valid_syllable(syllable: &str) -> bool {
+    SYLLABLES.split_whitespace().any(|valid| valid == syllable)
+}
+
+/// Layout of a pinyin mapping file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingFormat {
+    /// A syllable followed by the chars read as it on every line,
+    /// separated by whitespace, the syllable may carry a tone number or
+    /// tone marks
+    Table,
+    /// Tab separated Unihan readings like `U+4E2D kHanyuPinyin 10015.020:zhōng,zhòng`
+    /// from the `kHanyuPinyin`, `kMandarin`, `kXHC1983` and `kHanyuPinlu` fields
+    Unihan,
+    /// CC-CEDICT entries like `中 中 [zhong1] /middle/`, entries of more
+    /// than one char are skipped
+    Cedict,
+}
+
+impl FromStr for MappingFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(MappingFormat::Table),
+            "unihan" => Ok(MappingFormat::Unihan),
+            "cedict" => Ok(MappingFormat::Cedict),
+            _ => Err(format!("unknown mapping format {:?}", s)),
+        }
+    }
+}
+
+/// Something in a mapping file that was skipped or merged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingWarning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for MappingWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Syllables and the chars read as them, loaded from a mapping file
+#[derive(Debug, Clone, Default)]
+pub struct MappingFile {
+    /// Chars of every toneless syllable, whatever their tone
+    pub mapping: BTreeMap<String, Vec<char>>,
+    /// Chars of every syllable with a tone number like "zhong1", for the
+    /// readings that had a tone
+    pub tones: BTreeMap<String, Vec<char>>,
+    pub warnings: Vec<MappingWarning>,
+    /// Syllables of the table lines so far, as written
+    listed: BTreeSet<String>,
+}
+
+impl MappingFile {
+    pub fn load_from_path<P: AsRef<Path>>(
+        path: P,
+        format: MappingFormat,
+        encoding: Encoding,
+    ) -> Result<Self, Error> {
+        Self::load_from_reader(File::open(path)?, format, encoding)
+    }
+
+    /// Read a mapping file of `format`, `Encoding::Auto` picks UTF-8 if the
+    /// whole file is valid UTF-8 and GBK otherwise
+    ///
+    /// A table with an invalid syllable or a candidate of more than one char
+    /// is an error, chars listed twice are merged with a warning. Readings
+    /// of the other formats that are not valid syllables are skipped with a
+    /// warning.
+    pub fn load_from_reader<R: Read>(
+        reader: R,
+        format: MappingFormat,
+        encoding: Encoding,
+    ) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        BufReader::new(reader).read_to_end(&mut bytes)?;
+        // short GBK lines are often valid UTF-8, so decide for the whole file
+        let encoding = match encoding {
+            Encoding::Auto if std::str::from_utf8(&bytes).is_ok() => Encoding::Utf8,
+            Encoding::Auto => Encoding::Gbk,
+            encoding => encoding,
+        };
+        let mut lines = crate::corpus::Lines::new(bytes.as_slice(), encoding);
+        let mut res = MappingFile::default();
+        while let Some(line) = lines.next_line()? {
+            let line = line.trim_start_matches('\u{feff}').trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            match format {
+                MappingFormat::Table => res.add_table_line(line, lines.number)?,
+                MappingFormat::Unihan => res.add_unihan_line(line, lines.number),
+                MappingFormat::Cedict => res.add_cedict_line(line, lines.number),
+            }
+        }
+        Ok(res)
+    }
+
+    /// All chars of the mapping
+    pub fn chars(&self) -> BTreeSet<char> {
+        self.mapping.values().flatten().copied().collect()
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(MappingWarning { line, message });
+    }
+
+    /// Add `ch` as read as the toneless `syllable` with `tone`, whether it
+    /// was new for that tone
+    fn insert(&mut self, syllable: &str, tone: Option<u8>, ch: char) -> bool {
+        let mut new = push_new(self.mapping.entry(syllable.to_string()).or_default(), ch);
+        if let Some(tone) = tone {
+            new = push_new(self.tones.entry(numbered(syllable, tone)).or_default(), ch);
+        }
+        new
+    }
+
+    fn add_table_line(&mut self, line: &str, number: usize) -> Result<(), Error> {
+        let invalid = |reason| Error::InvalidMapping {
+            line: number,
+            reason,
+        };
+        let mut words = line.split_whitespace();
+        let word = words.next().unwrap_or("");
+        let (syllable, tone) = parse_syllable(word);
+        if !is_valid_syllable(&syllable) {
+            return Err(invalid(format!("{:?} is not a pinyin syllable", word)));
+        }
+        let key = tone.map_or_else(|| syllable.clone(), |tone| numbered(&syllable, tone));
+        if !self.listed.insert(key.clone()) {
+            self.warn(number, format!("syllable {:?} listed again, merged", key));
+        }
+        let mut empty = true;
+        for word in words {
+            let mut chars = word.chars();
+            let ch = chars
+                .next()
+                .expect("split_whitespace yields no empty words");
+            if chars.next().is_some() {
+                return Err(invalid(format!(
+                    "candidate {:?} is not a single char",
+                    word
+                )));
+            }
+            empty = false;
+            if !self.insert(&syllable, tone, ch) {
+                self.warn(number, format!("{:?} listed twice for {:?}", ch, key));
+            }
+        }
+        if empty {
+            self.warn(number, format!("no chars for {:?}, skipped", key));
+        }
+        Ok(())
+    }
+
+    /// Add the readings of `ch`, skipping invalid ones with a warning
+    fn add_readings<'a, I>(&mut self, ch: char, readings: I, number: usize)
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        for reading in readings {
+            let (syllable, tone) = parse_syllable(reading);
+            if is_valid_syllable(&syllable) {
+                self.insert(&syllable, tone, ch);
+            } else {
+                self.warn(
+                    number,
+                    format!("{:?} of {:?} is not a pinyin syllable", reading, ch),
+                );
+            }
+        }
+    }
+
+    fn add_unihan_line(&mut self, line: &str, number: usize) {
+        let mut fields = line.split('\t');
+        let (code, field, value) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(code), Some(field), Some(value)) => (code.trim(), field.trim(), value),
+            _ => {
+                self.warn(number, "not a unihan entry".to_string());
+                return;
+            }
+        };
+        if !["kHanyuPinyin", "kMandarin", "kXHC1983", "kHanyuPinlu"].contains(&field) {
+            return;
+        }
+        let ch = match code
+            .strip_prefix("U+")
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(std::char::from_u32)
+        {
+            Some(ch) => ch,
+            None => {
+                self.warn(number, format!("invalid code point {:?}", code));
+                return;
+            }
+        };
+        // "10015.020,10015.030:zhōng,zhòng" or "zhōng(117)"
+        let readings = value.split_whitespace().flat_map(|item| {
+            let item = item.rsplit(':').next().unwrap_or(item);
+            item.split(',').map(|reading| match reading.find('(') {
+                Some(pos) => &reading[..pos],
+                None => reading,
+            })
+        });
+        let readings: Vec<&str> = readings.filter(|reading| !reading.is_empty()).collect();
+        self.add_readings(ch, readings.into_iter(), number);
+    }
+
+    fn add_cedict_line(&mut self, line: &str, number: usize) {
+        let mut words = line.split_whitespace();
+        let simplified = match (words.next(), words.next()) {
+            (Some(_), Some(simplified)) => simplified,
+            _ => {
+                self.warn(number, "not a cedict entry".to_string());
+                return;
+            }
+        };
+        let reading = match (line.find('['), line.find(']')) {
+            (Some(start), Some(end)) if start < end => &line[start + 1..end],
+            _ => {
+                self.warn(number, "no reading in brackets".to_string());
+                return;
+            }
+        };
+        let mut chars = simplified.chars();
+        let ch = match (chars.next(), chars.next()) {
+            (Some(ch), None) if is_hanzi(ch) => ch,
+            _ => return,
+        };
+        if reading.split_whitespace().count() == 1 {
+            self.add_readings(ch, std::iter::once(reading.trim()), number);
+        }
+    }
+}
+
+/// Push `ch` unless `chars` has it already, whether it was new
+fn push_new(chars: &mut Vec<char>, ch: char) -> bool {
+    if chars.contains(&ch) {
+        return false;
+    }
+    chars.push(ch);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &[u8], format: MappingFormat) -> Result<MappingFile, Error> {
+        MappingFile::load_from_reader(text, format, Encoding::Auto)
+    }
+
+    fn warning_lines(mapping: &MappingFile) -> Vec<usize> {
+        mapping
+            .warnings
+            .iter()
+            .map(|warning| warning.line)
+            .collect()
+    }
+
+    #[test]
+    fn table_lines() {
+        let text = "\u{feff}zhong1 中 钟\n# zhong1 忠\nzhong1 中\nnu:3 女\n";
+        let mapping = load(text.as_bytes(), MappingFormat::Table).unwrap();
+        assert_eq!(mapping.mapping["zhong"], vec!['中', '钟']);
+        assert_eq!(mapping.tones["zhong1"], vec!['中', '钟']);
+        assert_eq!(mapping.mapping["nv"], vec!['女']);
+        assert_eq!(mapping.tones["nv3"], vec!['女']);
+        // the syllable listed again and its char listed twice
+        assert_eq!(warning_lines(&mapping), vec![3, 3]);
+
+        assert!(matches!(
+            load("zhong1 中\nguo2 国 中国\n".as_bytes(), MappingFormat::Table),
+            Err(Error::InvalidMapping { line: 2, .. })
+        ));
+
+        // "zhong 中" in GBK is not valid UTF-8
+        let mapping = load(b"zhong \xd6\xd0\n", MappingFormat::Table).unwrap();
+        assert_eq!(mapping.mapping["zhong"], vec!['中']);
+        assert!(mapping.tones.is_empty());
+    }
+
+    #[test]
+    fn unihan_lines() {
+        let text = "# Unihan\n\
+                    U+4E2D\tkHanyuPinyin\t10015.020:zhōng,zhòng\n\
+                    U+4E2D\tkDefinition\tcenter\n\
+                    U+5973\tkMandarin\tnǚ\n\
+                    U+5973\tkHanyuPinlu\tnü(117)\n\
+                    U+4E00\tkMandarin\tqq\n";
+        let mapping = load(text.as_bytes(), MappingFormat::Unihan).unwrap();
+        assert_eq!(mapping.mapping["zhong"], vec!['中']);
+        assert_eq!(mapping.tones["zhong1"], vec!['中']);
+        assert_eq!(mapping.tones["zhong4"], vec!['中']);
+        assert_eq!(mapping.mapping["nv"], vec!['女']);
+        assert_eq!(mapping.tones["nv3"], vec!['女']);
+        assert_eq!(mapping.chars().len(), 2);
+        assert_eq!(warning_lines(&mapping), vec![6]);
+    }
+
+    #[test]
+    fn cedict_lines() {
+        let text = "# CC-CEDICT\n\
+                    中國 中国 [Zhong1 guo2] /China/\n\
+                    中 中 [zhong1] /middle/\n\
+                    女 女 [nu:3] /woman/\n\
+                    % % [pa1] /percent/\n\
+                    好 好 [xx3] /good/\n\
+                    broken\n";
+        let mapping = load(text.as_bytes(), MappingFormat::Cedict).unwrap();
+        assert_eq!(mapping.mapping["zhong"], vec!['中']);
+        assert_eq!(mapping.tones["zhong1"], vec!['中']);
+        assert_eq!(mapping.mapping["nv"], vec!['女']);
+        assert_eq!(mapping.tones["nv3"], vec!['女']);
+        // entries of more than one char are skipped
+        assert!(!mapping.mapping.contains_key("guo"));
+        assert_eq!(warning_lines(&mapping), vec![6, 7]);
+    }
+}
@@ -0,0 +1,335 @@
+use super::*;
+use std::collections::BTreeSet;
+
+/// Scale `weights` to sum up to 1
+fn normalized(weights: &[f64], inputs: usize) -> Result<Vec<f64>, Error> {
+    let invalid = |reason: String| Err(Error::InvalidSmoothing(reason));
+    if weights.len() != inputs {
+        return invalid(format!("{} weights for {} inputs", weights.len(), inputs));
+    }
+    if let Some(weight) = weights
+        .iter()
+        .find(|weight| !weight.is_finite() || **weight < 0.0)
+    {
+        return invalid(format!("invalid weight {}", weight));
+    }
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return invalid("weights must not all be zero".to_string());
+    }
+    Ok(weights.iter().map(|weight| weight / total).collect())
+}
+
+/// Union of the chars of every syllable, in the order they first appear
+fn merge_mappings<'a, I>(mappings: I) -> BTreeMap<String, Vec<char>>
+where
+    I: Iterator<Item = &'a BTreeMap<String, Vec<char>>>,
+{
+    let mut res: BTreeMap<String, Vec<char>> = BTreeMap::new();
+    for mapping in mappings {
+        for (syllable, chars) in mapping {
+            let merged = res.entry(syllable.clone()).or_default();
+            for ch in chars {
+                if !merged.contains(ch) {
+                    merged.push(*ch);
+                }
+            }
+        }
+    }
+    res
+}
+
+impl<T: Match> Model<T> {
+    /// Static interpolation of backoff models, `weights` are normalized to
+    /// sum up to 1
+    ///
+    /// `lower[i]` are the models of orders 1 to `T::order() - 1` of the same
+    /// input as `models[i]`. Every n-gram stored in any of `models` gets
+    /// `Σ weights[i] * P_i(w|h)`, where `P_i` is the Katz backoff probability
+    /// of input i. No backoff weights are stored, see `normalize_backoffs`.
+    pub fn interpolate(
+        models: &[&Model<T>],
+        lower: &[Vec<&dyn LanguageModel>],
+        weights: &[f64],
+    ) -> Result<Self, Error> {
+        assert_eq!(models.len(), lower.len());
+        let weights = normalized(weights, models.len())?;
+        let mut res =
+            Self::from_mapping(merge_mappings(models.iter().map(|model| &model.mapping)))?;
+
+        let inputs: Vec<Vec<&dyn LanguageModel>> = models
+            .iter()
+            .zip(lower)
+            .map(|(model, lower)| {
+                assert_eq!(lower.len() + 1, T::order());
+                let mut input = lower.clone();
+                input.push(*model);
+                input
+            })
+            .collect();
+        let ngrams: BTreeSet<T> = models
+            .iter()
+            .flat_map(|model| model.probs().map(|(ngram, _)| ngram))
+            .collect();
+        for ngram in &ngrams {
+            let (ch, history) = ngram.chars().split_last().expect("empty n-gram");
+            let prob = inputs
+                .iter()
+                .zip(&weights)
+                .map(|(input, weight)| weight * backoff_prob(input, history, *ch))
+                .sum();
+            res.insert_prob(ngram, prob)?;
+        }
+
+        res.metadata.smoothing = "interpolated".to_string();
+        res.metadata.tones = merge_mappings(models.iter().map(|model| &model.metadata.tones));
+        res.metadata.corpus = models
+            .iter()
+            .zip(&weights)
+            .map(|(model, weight)| format!("{} * ({})", weight, model.metadata.corpus))
+            .collect::<Vec<_>>()
+            .join(" + ");
+        Ok(res)
+    }
+}
+
+/// Models of order 1, 2 and 3
+pub type ModelOrders = (Model<Match1>, Model<Match2>, Model<Match3>);
+
+/// Static interpolation of backoff models of order 1 to 3, with the backoff
+/// weights recomputed to normalize every context of the result
+///
+/// The result gives the stored n-grams the probability the linear
+/// interpolation of the inputs with Katz smoothing gives them, which is what
+/// `tune_weights` maximizes the likelihood of.
+///
+/// The inputs should be normalized like Kneser-Ney or pruned models. A
+/// context whose stored n-grams take all of the probability, as in maximum
+/// likelihood models, gets the smallest backoff weight instead of zero.
+pub fn interpolate_models(
+    inputs: &[(&Model<Match1>, &Model<Match2>, &Model<Match3>)],
+    weights: &[f64],
+) -> Result<ModelOrders, Error> {
+    let models1: Vec<&Model<Match1>> = inputs.iter().map(|input| input.0).collect();
+    let models2: Vec<&Model<Match2>> = inputs.iter().map(|input| input.1).collect();
+    let models3: Vec<&Model<Match3>> = inputs.iter().map(|input| input.2).collect();
+    let lower1: Vec<Vec<&dyn LanguageModel>> = inputs.iter().map(|_| Vec::new()).collect();
+    let lower2: Vec<Vec<&dyn LanguageModel>> =
+        inputs.iter().map(|input| vec![input.0 as _]).collect();
+    let lower3: Vec<Vec<&dyn LanguageModel>> = inputs
+        .iter()
+        .map(|input| vec![input.0 as _, input.1 as _])
+        .collect();
+
+    let mut model1 = Model::interpolate(&models1, &lower1, weights)?;
+    let mut model2 = Model::interpolate(&models2, &lower2, weights)?;
+    let model3 = Model::interpolate(&models3, &lower3, weights)?;
+    normalize_backoffs(&[], &mut model1, &model2);
+    normalize_backoffs(&[&model1], &mut model2, &model3);
+    Ok((model1, model2, model3))
+}
+
+impl JsonCounts {
+    /// Add up `counts` scaled by `weights`, rounding to whole counts, and
+    /// return the number of n-grams dropped because their count rounds to 0
+    ///
+    /// Weights are normalized so that their mean is 1, keeping the total
+    /// count in the same range.
+    pub fn merge(counts: &[JsonCounts], weights: &[f64]) -> Result<(Self, usize), Error> {
+        let weights: Vec<f64> = normalized(weights, counts.len())?
+            .iter()
+            .map(|weight| weight * counts.len() as f64)
+            .collect();
+        let order = counts.iter().map(JsonCounts::order).max().unwrap_or(0);
+        let mut corpus = Vec::new();
+        let mut scaled: Vec<BTreeMap<String, f64>> = vec![BTreeMap::new(); order];
+        for (json_counts, weight) in counts.iter().zip(&weights) {
+            corpus.extend(
+                json_counts
+                    .corpus
+                    .iter()
+                    .map(|corpus| format!("{} * ({})", weight, corpus)),
+            );
+            for (table, other) in scaled.iter_mut().zip(&json_counts.counts) {
+                for (ngram, count) in other {
+                    *table.entry(ngram.clone()).or_insert(0.0) += *count as f64 * weight;
+                }
+            }
+        }
+        let mut dropped = 0;
+        let tables = scaled
+            .into_iter()
+            .map(|table| {
+                let len = table.len();
+                let table: BTreeMap<String, u32> = table
+                    .into_iter()
+                    .map(|(ngram, count)| (ngram, count.round() as u32))
+                    .filter(|(_, count)| *count > 0)
+                    .collect();
+                dropped += len - table.len();
+                table
+            })
+            .collect();
+        let merged = JsonCounts {
+            corpus,
+            mapping: merge_mappings(counts.iter().map(|counts| &counts.mapping)),
+            counts: tables,
+        };
+        Ok((merged, dropped))
+    }
+}
+
+/// Weights maximizing the likelihood of `sentences` under the linear
+/// interpolation of `models`, found by expectation maximization, and the
+/// average log probability per char with them
+///
+/// Chars that are in no mapping or that no model can produce are skipped
+/// and break the history. The weights stay uniform if there is no other char.
+/// To tune the weights of `interpolate_models`, pass each input with Katz
+/// smoothing.
+pub fn tune_weights(models: &[&dyn LanguageModel], sentences: &[&str]) -> (Vec<f64>, f64) {
+    let valid: BTreeSet<char> = models
+        .iter()
+        .flat_map(|model| model.mapping().values().flatten().copied())
+        .collect();
+    // probability of every char under every model
+    let mut events: Vec<Vec<f64>> = Vec::new();
+    for sentence in sentences {
+        let mut history: Vec<char> = Vec::new();
+        for ch in sentence.chars() {
+            if !valid.contains(&ch) {
+                history.clear();
+                continue;
+            }
+            let probs: Vec<f64> = models
+                .iter()
+                .map(|model| {
+                    let len = history.len().min(model.order() - 1);
+                    model
+                        .log_prob(&history[history.len() - len..], ch)
+                        .map_or(0.0, f64::exp)
+                })
+                .collect();
+            if probs.iter().all(|prob| *prob == 0.0) {
+                history.clear();
+                continue;
+            }
+            events.push(probs);
+            history.push(ch);
+        }
+    }
+
+    let mut weights = vec![1.0 / models.len() as f64; models.len()];
+    if events.is_empty() {
+        return (weights, 0.0);
+    }
+    let mut log_likelihood = f64::NEG_INFINITY;
+    for _ in 0..100 {
+        let mut expected = vec![0.0; models.len()];
+        let mut total = 0.0;
+        for probs in &events {
+            let mixed: f64 = probs.iter().zip(&weights).map(|(p, w)| p * w).sum();
+            total += mixed.ln();
+            for (i, p) in probs.iter().enumerate() {
+                expected[i] += weights[i] * p / mixed;
+            }
+        }
+        let next = total / events.len() as f64;
+        weights = expected
+            .iter()
+            .map(|expected| expected / events.len() as f64)
+            .collect();
+        let converged = next - log_likelihood < 1e-9;
+        log_likelihood = next;
+        if converged {
+            break;
+        }
+    }
+    (weights, log_likelihood)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn input(unigrams: &[(&str, f64)], bigrams: &[(&str, f64)]) -> ModelOrders {
+        let mut model1 = model(unigrams);
+        let model2 = model(bigrams);
+        normalize_backoffs(&[], &mut model1, &model2);
+        (model1, model2, model(&[]))
+    }
+
+    #[test]
+    fn interpolated_models_are_normalized() {
+        let a = input(&[("x", 0.5), ("y", 0.3), ("z", 0.2)], &[("xy", 0.6)]);
+        let b = input(
+            &[("x", 0.2), ("y", 0.2), ("z", 0.6)],
+            &[("xz", 0.5), ("yx", 0.4)],
+        );
+        let (model1, model2, _) =
+            interpolate_models(&[(&a.0, &a.1, &a.2), (&b.0, &b.1, &b.2)], &[1.0, 3.0]).unwrap();
+        let merged: [&dyn LanguageModel; 2] = [&model1, &model2];
+
+        // P_b(y|x) backs off to the unigram with the weight left by "xz"
+        let expected = 0.25 * 0.6 + 0.75 * 0.5 / 0.4 * 0.2;
+        assert!((backoff_prob(&merged, &['x'], 'y') - expected).abs() < 1e-9);
+        for context in &['x', 'y', 'z'] {
+            let total: f64 = ['x', 'y', 'z']
+                .iter()
+                .map(|ch| backoff_prob(&merged, &[*context], *ch))
+                .sum();
+            assert!((total - 1.0).abs() < 1e-9, "{}: {}", context, total);
+        }
+    }
+
+    #[test]
+    fn unnormalized_contexts_keep_backing_off() {
+        let mut a = input(&[("x", 0.5), ("y", 0.3), ("z", 0.2)], &[]);
+        a.1 = model(&[("xy", 1.0)]);
+        let (model1, model2, _) = interpolate_models(&[(&a.0, &a.1, &a.2)], &[1.0]).unwrap();
+        let merged: [&dyn LanguageModel; 2] = [&model1, &model2];
+        assert!(model1.get_backoff(&Match1::from_chars(&['x'])).unwrap() > 0.0);
+        assert!(backoff_prob(&merged, &['x'], 'z') > 0.0);
+    }
+
+    #[test]
+    fn invalid_weights_are_an_error() {
+        let a = input(&[("x", 1.0)], &[]);
+        let inputs = [(&a.0, &a.1, &a.2), (&a.0, &a.1, &a.2)];
+        for weights in &[&[0.0, 0.0][..], &[1.0, -1.0], &[1.0, f64::NAN], &[1.0]] {
+            assert!(matches!(
+                interpolate_models(&inputs, weights),
+                Err(Error::InvalidSmoothing(_))
+            ));
+        }
+        assert!(matches!(
+            JsonCounts::merge(&[JsonCounts::default(), JsonCounts::default()], &[0.0, 0.0]),
+            Err(Error::InvalidSmoothing(_))
+        ));
+    }
+
+    #[test]
+    fn merged_counts_report_dropped_ngrams() {
+        let counts = |table: &[(&str, u32)]| JsonCounts {
+            counts: vec![table
+                .iter()
+                .map(|(ngram, count)| (ngram.to_string(), *count))
+                .collect()],
+            ..JsonCounts::default()
+        };
+        let (merged, dropped) = JsonCounts::merge(
+            &[
+                counts(&[("x", 4)]),
+                counts(&[("x", 10), ("y", 3), ("z", 1)]),
+            ],
+            &[9.0, 1.0],
+        )
+        .unwrap();
+        // the weights are scaled to 1.8 and 0.2
+        assert_eq!(merged.counts[0]["x"], 9);
+        assert_eq!(merged.counts[0]["y"], 1);
+        assert!(!merged.counts[0].contains_key("z"));
+        assert_eq!(dropped, 1);
+    }
+}
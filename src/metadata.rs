@@ -0,0 +1,60 @@
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the model file layout, bumped on incompatible changes
+pub const MODEL_VERSION: u32 = 1;
+
+/// Description of a model, stored at the beginning of the model file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub version: u32,
+    /// Number of chars in every n-gram of the model
+    pub order: usize,
+    /// How the probabilities were estimated, e.g. "mle" or "kneser-ney"
+    pub smoothing: String,
+    /// Free-form description of the training data
+    pub corpus: String,
+    /// Seconds since the unix epoch, 0 if unknown
+    pub created: u64,
+    /// Number of distinct chars in the mapping and the n-grams
+    pub vocab_size: usize,
+    /// Chars of the numbered toned syllables like "zhong1" of the mapping
+    /// file, empty if it lists no tones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tones: BTreeMap<String, Vec<char>>,
+}
+
+impl Metadata {
+    /// Metadata of a model of `order` created now
+    pub fn new(order: usize) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        Metadata {
+            version: MODEL_VERSION,
+            order,
+            smoothing: "unknown".to_string(),
+            corpus: String::new(),
+            created,
+            vocab_size: 0,
+            tones: BTreeMap::new(),
+        }
+    }
+
+    /// Check that a model with this metadata can be loaded as a model of `order`
+    pub fn validate(&self, order: usize) -> Result<(), Error> {
+        if self.version != MODEL_VERSION {
+            return Err(Error::InvalidModel(format!(
+                "format version {}, expected {}",
+                self.version, MODEL_VERSION
+            )));
+        }
+        if self.order != order {
+            return Err(Error::InvalidModel(format!(
+                "model of order {} loaded as order {}",
+                self.order, order
+            )));
+        }
+        Ok(())
+    }
+}
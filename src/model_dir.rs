@@ -0,0 +1,37 @@
+use super::*;
+
+/// Load the model of `T::order()` from `dir` for decoding or scoring
+///
+/// `model{n}.bin` is memory-mapped if present, otherwise `model{n}.json.gz`
+/// is loaded. Without `dir`, the bundled model is used if the library is
+/// built with it, otherwise the current directory.
+pub fn load_model<T: Match + 'static>(dir: Option<&Path>) -> Result<Rc<dyn LanguageModel>, Error> {
+    let dir = match dir {
+        Some(dir) => dir,
+        #[cfg(feature = "bundled")]
+        None => return Ok(Rc::new(Model::<T>::load()?)),
+        #[cfg(not(feature = "bundled"))]
+        None => Path::new("."),
+    };
+    let binary = dir.join(BinaryModel::file_name(T::order()));
+    if binary.exists() {
+        let model = BinaryModel::load_from_path(&binary)?;
+        model.metadata().validate(T::order())?;
+        return Ok(Rc::new(model));
+    }
+    Ok(Rc::new(Model::<T>::load_from_path(
+        dir.join(Model::<T>::file_name()),
+    )?))
+}
+
+impl<T: Match> Model<T> {
+    /// Load the model of this order from `dir` like `load_model`, a binary
+    /// model is converted back and keeps its quantization error
+    pub fn load_from_dir(dir: &Path) -> Result<Self, Error> {
+        let binary = dir.join(BinaryModel::file_name(T::order()));
+        if binary.exists() {
+            return Self::from_json(BinaryModel::load_from_path(binary)?.to_json()?);
+        }
+        Self::load_from_path(dir.join(Self::file_name()))
+    }
+}
@@ -0,0 +1,45 @@
+use super::*;
+use std::fmt::{self, Display};
+
+/// A sequence of `N` consecutive chinese characters
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct NGram<const N: usize>([char; N]);
+
+pub type Match1 = NGram<1>;
+pub type Match2 = NGram<2>;
+pub type Match3 = NGram<3>;
+
+impl<const N: usize> Display for NGram<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ch in self.0.iter() {
+            write!(f, "{}", ch)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Match for NGram<N> {
+    fn order() -> usize {
+        N
+    }
+
+    fn new(history: &[char], end: char) -> Self {
+        assert!(history.len() + 1 >= N);
+        let mut res = [end; N];
+        res[..N - 1].copy_from_slice(&history[history.len() + 1 - N..]);
+        NGram(res)
+    }
+
+    fn chars(&self) -> &[char] {
+        &self.0
+    }
+
+    fn from_str(s: &str) -> Self {
+        let mut res = [' '; N];
+        let mut chars = s.chars();
+        for ch in res.iter_mut() {
+            *ch = chars.next().expect("too short");
+        }
+        NGram(res)
+    }
+}
@@ -0,0 +1,36 @@
+use super::*;
+
+/// Receives the internals of the decoder, all methods do nothing by default
+pub trait Observer {
+    /// The syllable graph about to be decoded
+    fn graph(&mut self, _graph: &SyllableGraph) {}
+
+    /// Nodes kept for the syllables ending at position `end`
+    fn step(&mut self, _end: usize, _nodes: &[Node]) {}
+
+    /// Nodes of the best path once the lattice is complete
+    fn path(&mut self, _nodes: &[Node]) {}
+}
+
+/// Observe nothing
+impl Observer for () {}
+
+/// Send the decoder internals to the `log` crate at debug level
+#[cfg(feature = "log")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogObserver;
+
+#[cfg(feature = "log")]
+impl Observer for LogObserver {
+    fn graph(&mut self, graph: &SyllableGraph) {
+        log::debug!("graph of {} letters: {:?}", graph.len, graph.edges);
+    }
+
+    fn step(&mut self, end: usize, nodes: &[Node]) {
+        log::debug!("step {}: {:?}", end, nodes);
+    }
+
+    fn path(&mut self, nodes: &[Node]) {
+        log::debug!("path: {:?}", nodes);
+    }
+}
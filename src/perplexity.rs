@@ -0,0 +1,150 @@
+use super::*;
+use std::collections::BTreeSet;
+
+/// Whether `ch` is a CJK unified ideograph, the chars a model should cover
+pub fn is_hanzi(ch: char) -> bool {
+    matches!(ch,
+        '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2ffff}')
+}
+
+/// Log-likelihood of a held-out text under a model
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Perplexity {
+    /// Number of chars scored
+    pub chars: usize,
+    /// Sum of the natural log probabilities of the scored chars
+    pub log_prob: f64,
+    /// Scored chars the model gives no probability, counted with
+    /// `UNSEEN_PROB` like the decoder does
+    pub unseen: usize,
+    /// Hanzi outside the mapping of the model, not scored
+    pub oov: usize,
+}
+
+impl Perplexity {
+    /// Add the score of one char, `None` if the model gives it no probability
+    pub fn add(&mut self, log_prob: Option<f64>) {
+        self.chars += 1;
+        self.log_prob += match log_prob {
+            Some(log_prob) => log_prob,
+            None => {
+                self.unseen += 1;
+                UNSEEN_PROB.ln()
+            }
+        };
+    }
+
+    /// Add the scores of another text
+    pub fn merge(&mut self, other: &Perplexity) {
+        self.chars += other.chars;
+        self.log_prob += other.log_prob;
+        self.unseen += other.unseen;
+        self.oov += other.oov;
+    }
+
+    /// Average natural log probability per scored char
+    pub fn log_prob_per_char(&self) -> f64 {
+        self.log_prob / self.chars.max(1) as f64
+    }
+
+    /// Cross-entropy in bits per char
+    pub fn cross_entropy(&self) -> f64 {
+        -self.log_prob_per_char() / std::f64::consts::LN_2
+    }
+
+    pub fn perplexity(&self) -> f64 {
+        (-self.log_prob_per_char()).exp()
+    }
+}
+
+/// Chars of every syllable of `mapping`
+fn mapped_chars(mapping: &BTreeMap<String, Vec<char>>) -> BTreeSet<char> {
+    mapping.values().flatten().copied().collect()
+}
+
+/// Number of hanzi in `text` that are not in `valid`
+fn count_oov(text: &str, valid: &BTreeSet<char>) -> usize {
+    text.chars()
+        .filter(|ch| is_hanzi(*ch) && !valid.contains(ch))
+        .count()
+}
+
+/// Score the chars of `text` in the mapping of `model` given up to
+/// `model.order() - 1` chars before them, those with fewer only if
+/// `short_history`
+///
+/// Any other char breaks the history, like a sentence boundary.
+fn score<L: LanguageModel + ?Sized>(model: &L, text: &str, short_history: bool) -> Perplexity {
+    let valid = mapped_chars(model.mapping());
+    let mut res = Perplexity {
+        oov: count_oov(text, &valid),
+        ..Perplexity::default()
+    };
+    let mut history: Vec<char> = Vec::with_capacity(model.order());
+    for ch in text.chars() {
+        if !valid.contains(&ch) {
+            history.clear();
+            continue;
+        }
+        if short_history || history.len() + 1 == model.order() {
+            res.add(model.log_prob(&history, ch));
+        }
+        history.push(ch);
+        if history.len() >= model.order() {
+            history.remove(0);
+        }
+    }
+    res
+}
+
+/// Score every char of `text` in the mapping of `model` given the chars
+/// before it, up to `model.order() - 1` of them
+///
+/// Any other char breaks the history, like a sentence boundary.
+pub fn perplexity<L: LanguageModel + ?Sized>(model: &L, text: &str) -> Perplexity {
+    score(model, text, true)
+}
+
+/// Score the last char of every n-gram of `text` made of chars in the
+/// mapping by `model` alone, without backing off
+///
+/// The first `model.order() - 1` chars of a run of mapped chars have too
+/// little history and are not scored.
+pub fn ngram_perplexity<L: LanguageModel + ?Sized>(model: &L, text: &str) -> Perplexity {
+    score(model, text, false)
+}
+
+impl<const N: usize> Model<NGram<N>> {
+    /// Score `text` by this model alone, see `ngram_perplexity`
+    pub fn perplexity(&self, text: &str) -> Perplexity {
+        ngram_perplexity(self, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn unseen_and_oov_chars() {
+        let mut model = Model::<Match2>::from_mapping(mapping_of("一二三")).unwrap();
+        model.insert_prob(&Match2::from_str("一二"), 0.5).unwrap();
+        model.insert_prob(&Match2::from_str("二三"), 0.25).unwrap();
+        // 四 is out of the mapping and breaks the history
+        let text = "一二三四一三";
+
+        let scored = model.perplexity(text);
+        assert_eq!((scored.chars, scored.unseen, scored.oov), (3, 1, 1));
+        let log_prob = 0.5f64.ln() + 0.25f64.ln() + UNSEEN_PROB.ln();
+        assert!((scored.log_prob - log_prob).abs() < 1e-9);
+        assert!((scored.perplexity() - (-log_prob / 3.0).exp()).abs() < 1e-6);
+
+        // the first char of every run has no bigram
+        let scored = perplexity(&model, text);
+        assert_eq!((scored.chars, scored.unseen, scored.oov), (5, 3, 1));
+    }
+}
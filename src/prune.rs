@@ -0,0 +1,364 @@
+use super::*;
+use std::collections::{BTreeSet, HashSet};
+
+/// Smallest backoff mass, avoids dividing by zero when the stored n-grams of
+/// a context cover all of the lower order distribution, and zero backoff
+/// weights when they take all of the probability
+const MIN_MASS: f64 = 1e-12;
+
+/// What pruning removed from a model
+#[derive(Debug, Clone)]
+pub struct PruneReport {
+    /// Number of n-grams removed, `removed[n - 1]` of order n
+    pub removed: Vec<usize>,
+    /// Estimated relative entropy of the pruned model to the original one,
+    /// in nats per char
+    pub entropy: f64,
+}
+
+impl PruneReport {
+    pub fn new(order: usize) -> Self {
+        PruneReport {
+            removed: vec![0; order],
+            entropy: 0.0,
+        }
+    }
+
+    /// Number of n-grams removed of all orders
+    pub fn total(&self) -> usize {
+        self.removed.iter().sum()
+    }
+
+    /// Estimated relative increase of the perplexity, e.g. 0.01 if it is
+    /// 1% higher after pruning
+    pub fn perplexity_increase(&self) -> f64 {
+        self.entropy.exp_m1()
+    }
+}
+
+/// Probability of `ch` after `history` in the backoff model made of
+/// `models` of order 1, 2, ...
+pub(crate) fn backoff_prob(models: &[&dyn LanguageModel], history: &[char], ch: char) -> f64 {
+    let order = models.len().min(history.len() + 1);
+    let history = &history[history.len() + 1 - order..];
+    match models[order - 1].log_prob(history, ch) {
+        Some(log_prob) => log_prob.exp(),
+        None if order == 1 => UNSEEN_PROB,
+        None => {
+            let weight = models[order - 2].backoff(history).map_or(1.0, f64::exp);
+            weight * backoff_prob(&models[..order - 1], &history[1..], ch)
+        }
+    }
+}
+
+/// Probability of `chars` in a row in the backoff model made of `models`
+fn sequence_prob(models: &[&dyn LanguageModel], chars: &[char]) -> f64 {
+    (0..chars.len())
+        .map(|i| {
+            let history = &chars[i.saturating_sub(models.len() - 1)..i];
+            backoff_prob(models, history, chars[i]).ln()
+        })
+        .sum::<f64>()
+        .exp()
+}
+
+/// A stored n-gram with its probability and the one the lower orders give
+/// its last char
+struct Entry<T> {
+    ngram: T,
+    prob: f64,
+    lower: f64,
+}
+
+/// Probability left to the lower orders after a context: 1 minus the
+/// stored probabilities, and 1 minus what the lower orders give the same chars
+#[derive(Debug, Clone, Copy)]
+struct Mass {
+    left: f64,
+    lower_left: f64,
+}
+
+impl Mass {
+    /// Backoff weight normalizing the context
+    fn backoff(&self) -> f64 {
+        self.left.max(MIN_MASS) / self.lower_left.max(MIN_MASS)
+    }
+}
+
+/// Entries of `model` and the backoff mass of each of their contexts,
+/// `lower` are the models of orders 1 to `T::order() - 1`
+fn entries<T: Match>(
+    model: &Model<T>,
+    lower: &[&dyn LanguageModel],
+) -> (Vec<Entry<T>>, HashMap<Vec<char>, Mass>) {
+    assert_eq!(lower.len() + 1, T::order());
+    let mut masses: HashMap<Vec<char>, Mass> = HashMap::new();
+    let entries: Vec<Entry<T>> = model
+        .probs()
+        .map(|(ngram, prob)| {
+            let (ch, context) = ngram.chars().split_last().expect("empty n-gram");
+            let lower = backoff_prob(lower, &context[1..], *ch);
+            let mass = masses.entry(context.to_vec()).or_insert(Mass {
+                left: 1.0,
+                lower_left: 1.0,
+            });
+            mass.left -= prob;
+            mass.lower_left -= lower;
+            Entry { ngram, prob, lower }
+        })
+        .collect();
+    (entries, masses)
+}
+
+/// Relative entropy between the model with and without `entry`, whose
+/// context has `mass` and occurs with `context_prob` (Stolcke 1998)
+fn cost<T>(entry: &Entry<T>, mass: Mass, context_prob: f64) -> f64 {
+    let left = mass.left.max(0.0);
+    let backoff = (left + entry.prob) / (mass.lower_left + entry.lower).max(MIN_MASS);
+    let mut diff = entry.prob * (entry.lower.ln() + backoff.ln() - entry.prob.ln());
+    if left > 0.0 {
+        diff += left * (backoff.ln() - mass.backoff().ln());
+    }
+    -context_prob * diff
+}
+
+/// Costs of `entries` with the `masses` of their contexts
+fn costs<'a, T: Match + 'a, I>(
+    entries: I,
+    masses: &HashMap<Vec<char>, Mass>,
+    lower: &[&dyn LanguageModel],
+) -> Vec<f64>
+where
+    I: Iterator<Item = &'a Entry<T>>,
+{
+    let mut context_probs: HashMap<&[char], f64> = HashMap::new();
+    entries
+        .map(|entry| {
+            let context = entry.ngram.get_prefix();
+            let context_prob = *context_probs
+                .entry(context)
+                .or_insert_with(|| sequence_prob(lower, context));
+            cost(entry, masses[context], context_prob)
+        })
+        .collect()
+}
+
+impl<T: Match> Model<T> {
+    /// Prefixes of the stored n-grams, which must stay in the model of the
+    /// order below
+    pub fn contexts(&self) -> HashSet<Vec<char>> {
+        self.probs()
+            .map(|(ngram, _)| ngram.get_prefix().to_vec())
+            .collect()
+    }
+
+    /// Relative entropy between the model and the model without each of its
+    /// n-grams, `lower` are the models of orders 1 to `T::order() - 1`
+    pub fn pruning_costs(&self, lower: &[&dyn LanguageModel]) -> Vec<(T, f64)> {
+        let (entries, masses) = entries(self, lower);
+        let costs = costs(entries.iter(), &masses, lower);
+        entries
+            .into_iter()
+            .map(|entry| entry.ngram)
+            .zip(costs)
+            .collect()
+    }
+}
+
+/// Remove `ngrams` from `model` and store the backoff weights that keep
+/// their contexts normalized into `context_model`, the model of the order
+/// below, adding what was removed to `report`
+///
+/// `lowest` are the models of the orders below `context_model`. The entropy
+/// added to the report is estimated on the model before pruning.
+pub fn prune_ngrams<L: Match, T: Match>(
+    lowest: &[&dyn LanguageModel],
+    context_model: &mut Model<L>,
+    model: &mut Model<T>,
+    ngrams: &[T],
+    report: &mut PruneReport,
+) {
+    assert_eq!(L::order() + 1, T::order());
+    let ngrams: BTreeSet<&T> = ngrams.iter().collect();
+    let (removed, backoffs, entropy) = {
+        let mut lower = lowest.to_vec();
+        lower.push(&*context_model);
+        let (entries, mut masses) = entries(model, &lower);
+        let removed: Vec<&Entry<T>> = entries
+            .iter()
+            .filter(|entry| ngrams.contains(&entry.ngram))
+            .collect();
+        let entropy: f64 = costs(removed.iter().copied(), &masses, &lower).iter().sum();
+        let mut contexts = BTreeSet::new();
+        for entry in &removed {
+            let mass = masses.get_mut(entry.ngram.get_prefix()).unwrap();
+            mass.left += entry.prob;
+            mass.lower_left += entry.lower;
+            contexts.insert(entry.ngram.get_prefix());
+        }
+        let backoffs: Vec<(L, f64)> = contexts
+            .into_iter()
+            .map(|context| (L::from_chars(context), masses[context].backoff()))
+            .collect();
+        let removed: Vec<T> = removed.iter().map(|entry| entry.ngram.clone()).collect();
+        (removed, backoffs, entropy)
+    };
+    for ngram in &removed {
+        model.remove_prob(ngram);
+    }
+    store_backoffs(context_model, &backoffs);
+    report.removed[T::order() - 1] += removed.len();
+    report.entropy += entropy;
+}
+
+/// Store the backoff weights of contexts of stored n-grams into
+/// `context_model`
+fn store_backoffs<L: Match>(context_model: &mut Model<L>, backoffs: &[(L, f64)]) {
+    for (context, weight) in backoffs {
+        // contexts of stored n-grams are made of interned chars and fit
+        context_model
+            .insert_backoff(context, *weight)
+            .expect("context of a stored n-gram");
+    }
+}
+
+/// Backoff weights normalizing the contexts of `model`, `lowest` are the
+/// models of the orders below `context_model`
+fn backoff_weights<L: Match, T: Match>(
+    lowest: &[&dyn LanguageModel],
+    context_model: &Model<L>,
+    model: &Model<T>,
+) -> Vec<(L, f64)> {
+    assert_eq!(L::order() + 1, T::order());
+    let mut lower = lowest.to_vec();
+    lower.push(context_model);
+    let (_, masses) = entries(model, &lower);
+    masses
+        .iter()
+        .map(|(context, mass)| (L::from_chars(context), mass.backoff()))
+        .collect()
+}
+
+/// Recompute the backoff weights `context_model` stores for the contexts
+/// of `model`, after n-grams of the orders below were pruned
+///
+/// Contexts without a stored weight keep backing off with weight 1.
+pub fn renormalize<L: Match, T: Match>(
+    lowest: &[&dyn LanguageModel],
+    context_model: &mut Model<L>,
+    model: &Model<T>,
+) {
+    let backoffs: Vec<(L, f64)> = backoff_weights(lowest, context_model, model)
+        .into_iter()
+        .filter(|(context, _)| context_model.get_backoff(context).is_some())
+        .collect();
+    store_backoffs(context_model, &backoffs);
+}
+
+/// Store the backoff weights normalizing every context of `model` into
+/// `context_model`, whose own contexts must be normalized already
+pub fn normalize_backoffs<L: Match, T: Match>(
+    lowest: &[&dyn LanguageModel],
+    context_model: &mut Model<L>,
+    model: &Model<T>,
+) {
+    let backoffs = backoff_weights(lowest, context_model, model);
+    store_backoffs(context_model, &backoffs);
+}
+
+/// Remove the bigrams and trigrams whose removal changes the model least,
+/// measured by relative entropy, until at most `size` n-grams of all orders
+/// remain
+///
+/// Bigrams are only removed once no trigram extends them and unigrams are
+/// never removed, so more n-grams remain only if unigrams alone exceed `size`.
+pub fn prune_to_size(
+    model1: &mut Model<Match1>,
+    model2: &mut Model<Match2>,
+    model3: &mut Model<Match3>,
+    size: usize,
+    report: &mut PruneReport,
+) {
+    loop {
+        let total = model1.len() + model2.len() + model3.len();
+        if total <= size {
+            break;
+        }
+        let contexts = model3.contexts();
+        let costs3 = model3.pruning_costs(&[&*model1, &*model2]);
+        let costs2: Vec<(Match2, f64)> = model2
+            .pruning_costs(&[&*model1])
+            .into_iter()
+            .filter(|(ngram, _)| !contexts.contains(ngram.chars()))
+            .collect();
+        let mut ranked: Vec<(f64, bool, usize)> = costs3
+            .iter()
+            .enumerate()
+            .map(|(i, (_, cost))| (*cost, true, i))
+            .chain(
+                costs2
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, cost))| (*cost, false, i)),
+            )
+            .collect();
+        if ranked.is_empty() {
+            break;
+        }
+        ranked.sort_by(|a, b| a.0.total_cmp(&b.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+        ranked.truncate(total - size);
+        let mut pruned3 = Vec::new();
+        let mut pruned2 = Vec::new();
+        for (_, trigram, i) in ranked {
+            if trigram {
+                pruned3.push(costs3[i].0);
+            } else {
+                pruned2.push(costs2[i].0);
+            }
+        }
+        prune_ngrams(&[&*model1], model2, model3, &pruned3, report);
+        prune_ngrams(&[], model1, model2, &pruned2, report);
+        renormalize(&[&*model1], model2, model3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn pruned_contexts_stay_normalized() {
+        let mut model1 = model(&[("x", 0.5), ("y", 0.3), ("z", 0.2)]);
+        let mut model2 = model(&[("xy", 0.6), ("xz", 0.2), ("yx", 0.5), ("zz", 0.3)]);
+        let mut model3 = model(&[("xyz", 0.5), ("yxy", 0.4)]);
+        normalize_backoffs(&[], &mut model1, &model2);
+        normalize_backoffs(&[&model1], &mut model2, &model3);
+
+        let mut report = PruneReport::new(3);
+        prune_to_size(&mut model1, &mut model2, &mut model3, 6, &mut report);
+        assert_eq!(model1.len() + model2.len() + model3.len(), 6);
+        assert_eq!(report.removed[0], 0);
+        assert_eq!(report.total(), 3);
+        assert_eq!(report.removed[1], 4 - model2.len());
+        assert_eq!(report.removed[2], 2 - model3.len());
+        assert!(report.entropy >= 0.0);
+
+        let models: [&dyn LanguageModel; 3] = [&model1, &model2, &model3];
+        let chars = ['x', 'y', 'z'];
+        for a in &chars {
+            let total: f64 = chars
+                .iter()
+                .map(|ch| backoff_prob(&models[..2], &[*a], *ch))
+                .sum();
+            assert!((total - 1.0).abs() < 1e-9, "{}: {}", a, total);
+            for b in &chars {
+                let total: f64 = chars
+                    .iter()
+                    .map(|ch| backoff_prob(&models, &[*a, *b], *ch))
+                    .sum();
+                assert!((total - 1.0).abs() < 1e-9, "{}{}: {}", a, b, total);
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Chars the user may type to separate syllables explicitly
+pub const SEPARATORS: [char; 2] = ['\'', ' '];
+
+/// A syllable spanning the letters `start..end` of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub start: usize,
+    pub end: usize,
+    pub syllable: String,
+    /// Log weight added to the paths through this edge, 0 unless it is a
+    /// fuzzy alternative of the input
+    pub log_weight: f64,
+}
+
+/// All ways to split a pinyin input into syllables
+///
+/// Positions count letters only, separators take no space. Every edge lies
+/// on at least one path from `0` to `len`.
+#[derive(Debug, Clone, Default)]
+pub struct SyllableGraph {
+    pub len: usize,
+    pub edges: Vec<Edge>,
+}
+
+impl SyllableGraph {
+    /// A graph with a single path through already separated syllables
+    pub fn from_words(words: &[&str]) -> Self {
+        SyllableGraph {
+            len: words.len(),
+            edges: words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| Edge {
+                    start: i,
+                    end: i + 1,
+                    syllable: word.to_string(),
+                    log_weight: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether there is at least one segmentation
+    pub fn is_complete(&self) -> bool {
+        !self.edges.is_empty()
+    }
+
+    /// Concatenate `other` after the end of this graph
+    pub fn append(&mut self, other: &SyllableGraph) {
+        for edge in &other.edges {
+            self.edges.push(Edge {
+                start: edge.start + self.len,
+                end: edge.end + self.len,
+                syllable: edge.syllable.clone(),
+                log_weight: edge.log_weight,
+            });
+        }
+        self.len += other.len;
+    }
+
+    /// Edges ending at position `end`
+    pub fn ending_at(&self, end: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.end == end)
+    }
+
+    /// Enumerate every segmentation, beware that there can be exponentially many
+    pub fn segmentations(&self) -> Vec<Vec<&str>> {
+        let mut res = Vec::new();
+        let mut path = Vec::new();
+        if self.len > 0 {
+            self.walk(0, &mut path, &mut res);
+        }
+        res
+    }
+
+    fn walk<'a>(&'a self, start: usize, path: &mut Vec<&'a str>, res: &mut Vec<Vec<&'a str>>) {
+        if start == self.len {
+            res.push(path.clone());
+            return;
+        }
+        for edge in self.edges.iter().filter(|edge| edge.start == start) {
+            path.push(&edge.syllable);
+            self.walk(edge.end, path, res);
+            path.pop();
+        }
+    }
+}
+
+/// What to do with tokens that are not pinyin syllables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownPolicy {
+    /// Fail with `Error::UnknownSyllable`
+    Error,
+    /// Copy the token verbatim into the output
+    PassThrough,
+    /// Drop the token, the syllables around it are decoded as one sentence
+    Skip,
+    /// Drop the token and decode the syllables before and after it as
+    /// separate sentences
+    Split,
+}
+
+impl FromStr for UnknownPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(UnknownPolicy::Error),
+            "pass" => Ok(UnknownPolicy::PassThrough),
+            "skip" => Ok(UnknownPolicy::Skip),
+            "split" => Ok(UnknownPolicy::Split),
+            _ => Err(format!("unknown policy {:?}", s)),
+        }
+    }
+}
+
+/// A run of known syllables, or a token that is not pinyin
+#[derive(Debug, Clone)]
+pub enum Piece {
+    Syllables(SyllableGraph),
+    /// `position` is the index of `token` among the tokens of the input
+    Unknown {
+        token: String,
+        position: usize,
+    },
+}
+
+impl Piece {
+    /// Group already separated syllables into pieces, `known` tells whether
+    /// a syllable is in the mapping
+    pub fn from_words<F: Fn(&str) -> bool>(words: &[&str], known: F) -> Vec<Piece> {
+        let mut res = Vec::new();
+        let mut run: Vec<&str> = Vec::new();
+        for (position, word) in words.iter().enumerate() {
+            if known(word) {
+                run.push(word);
+            } else {
+                if !run.is_empty() {
+                    res.push(Piece::Syllables(SyllableGraph::from_words(&run)));
+                    run.clear();
+                }
+                res.push(Piece::Unknown {
+                    token: word.to_string(),
+                    position,
+                });
+            }
+        }
+        if !run.is_empty() {
+            res.push(Piece::Syllables(SyllableGraph::from_words(&run)));
+        }
+        res
+    }
+}
+
+/// Split unspaced pinyin like "woaibeijing" into syllables
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    syllables: BTreeSet<String>,
+    max_len: usize,
+}
+
+impl Segmenter {
+    pub fn new<I, S>(syllables: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let syllables: BTreeSet<String> = syllables.into_iter().map(Into::into).collect();
+        let max_len = syllables
+            .iter()
+            .map(|s| s.chars().count())
+            .max()
+            .unwrap_or(0);
+        Segmenter { syllables, max_len }
+    }
+
+    /// Find all segmentations of `input`, honoring apostrophes and spaces as
+    /// syllable boundaries, combining tone marks are composed first
+    pub fn segment(&self, input: &str) -> SyllableGraph {
+        let input = compose_tone_marks(&input.trim().to_lowercase());
+        let mut letters = Vec::new();
+        // whether a syllable may span letters[i - 1] and letters[i]
+        let mut joined = Vec::new();
+        let mut separated = true;
+        for ch in input.chars() {
+            if SEPARATORS.contains(&ch) {
+                separated = true;
+            } else {
+                joined.push(!separated);
+                letters.push(ch);
+                separated = false;
+            }
+        }
+        let len = letters.len();
+
+        // forward pass: every syllable starting at a reachable position
+        let mut reachable = vec![false; len + 1];
+        let mut edges = Vec::new();
+        if len > 0 {
+            reachable[0] = true;
+        }
+        for start in 0..len {
+            if !reachable[start] {
+                continue;
+            }
+            let mut syllable = String::new();
+            for end in start + 1..=len.min(start + self.max_len) {
+                if end > start + 1 && !joined[end - 1] {
+                    break;
+                }
+                syllable.push(letters[end - 1]);
+                if self.syllables.contains(&syllable) {
+                    reachable[end] = true;
+                    edges.push(Edge {
+                        start,
+                        end,
+                        syllable: syllable.clone(),
+                        log_weight: 0.0,
+                    });
+                }
+            }
+        }
+
+        // backward pass: keep only syllables on a complete path
+        let mut complete = vec![false; len + 1];
+        complete[len] = true;
+        edges.sort_by_key(|edge| std::cmp::Reverse(edge.start));
+        let mut kept = Vec::new();
+        for edge in edges {
+            if complete[edge.end] {
+                complete[edge.start] = true;
+                kept.push(edge);
+            }
+        }
+        kept.reverse();
+        SyllableGraph { len, edges: kept }
+    }
+
+    /// Split `input` into tokens at spaces and apostrophes, and segment the
+    /// runs of tokens that are made of syllables
+    pub fn pieces(&self, input: &str) -> Vec<Piece> {
+        let mut res = Vec::new();
+        let mut run: Vec<&str> = Vec::new();
+        let tokens = input
+            .trim()
+            .split(&SEPARATORS[..])
+            .filter(|token| !token.is_empty());
+        for (position, token) in tokens.enumerate() {
+            if self.segment(token).is_complete() {
+                run.push(token);
+            } else {
+                if !run.is_empty() {
+                    res.push(Piece::Syllables(self.segment(&run.join("'"))));
+                    run.clear();
+                }
+                res.push(Piece::Unknown {
+                    token: token.to_string(),
+                    position,
+                });
+            }
+        }
+        if !run.is_empty() {
+            res.push(Piece::Syllables(self.segment(&run.join("'"))));
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segmenter() -> Segmenter {
+        Segmenter::new(vec!["xi", "an", "xian", "wo", "ai", "bei", "jing", "n"])
+    }
+
+    #[test]
+    fn apostrophes_separate_syllables() {
+        let segmenter = segmenter();
+        let graph = segmenter.segment("xian");
+        let mut joined = graph.segmentations();
+        joined.sort();
+        assert_eq!(joined, vec![vec!["xi", "an"], vec!["xian"]]);
+        assert_eq!(
+            segmenter.segment("xi'an").segmentations(),
+            vec![vec!["xi", "an"]]
+        );
+        assert_eq!(
+            segmenter.segment("xi an").segmentations(),
+            vec![vec!["xi", "an"]]
+        );
+    }
+
+    #[test]
+    fn unspaced_input() {
+        let graph = segmenter().segment("woaibeijing");
+        assert_eq!(graph.len, 11);
+        assert_eq!(graph.segmentations(), vec![vec!["wo", "ai", "bei", "jing"]]);
+        // "n" alone is a syllable, but leaves "g" that is none
+        assert!(graph.edges.iter().all(|edge| edge.syllable != "n"));
+        assert!(!segmenter().segment("xig").is_complete());
+    }
+
+    #[test]
+    fn unknown_tokens_split_pieces() {
+        let pieces = segmenter().pieces("wo ai 2020 beijing");
+        assert_eq!(pieces.len(), 3);
+        match &pieces[1] {
+            Piece::Unknown { token, position } => {
+                assert_eq!(token, "2020");
+                assert_eq!(*position, 2);
+            }
+            piece => panic!("expected an unknown token, got {:?}", piece),
+        }
+        match &pieces[2] {
+            Piece::Syllables(graph) => {
+                assert_eq!(graph.segmentations(), vec![vec!["bei", "jing"]])
+            }
+            piece => panic!("expected syllables, got {:?}", piece),
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use super::*;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Probability given to a char that even the unigram model has never seen,
+/// so that a path is never dropped as long as the syllable has a candidate
+pub const UNSEEN_PROB: f64 = 1e-10;
+
+/// How `Smoothed` combines the models of different orders
+#[derive(Debug, Clone, PartialEq)]
+pub enum Smoothing {
+    /// Use the highest order model the history allows and drop unseen
+    /// n-grams, like chaining `Model::convert` calls
+    None,
+    /// Fall back to the lower order model, multiplying by `alpha` every
+    /// time, i.e. "stupid backoff"
+    Backoff(f64),
+    /// Back off to the lower order model with the weights stored in the
+    /// models, e.g. trained with Kneser-Ney smoothing
+    Katz,
+    /// Linear interpolation, `weights[i]` is the weight of the model of
+    /// order `i + 1`
+    ///
+    /// Near the beginning of a sentence only the lower order models apply,
+    /// their weights are renormalized then.
+    Interpolation(Vec<f64>),
+}
+
+impl Smoothing {
+    /// Check that alpha is in (0, 1] and that interpolation weights are
+    /// non-negative and not all zero
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: String| Err(Error::InvalidSmoothing(reason));
+        match self {
+            Smoothing::Backoff(alpha) if !(*alpha > 0.0 && *alpha <= 1.0) => {
+                invalid(format!("alpha {} is not in (0, 1]", alpha))
+            }
+            Smoothing::Interpolation(weights) => {
+                if let Some(weight) = weights
+                    .iter()
+                    .find(|weight| weight.is_nan() || **weight < 0.0)
+                {
+                    return invalid(format!("weight {} is negative", weight));
+                }
+                let total: f64 = weights.iter().sum();
+                if !(total > 0.0 && total.is_finite()) {
+                    return invalid(format!(
+                        "weights {:?} do not sum up to a positive number",
+                        weights
+                    ));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    /// Parse `none`, `backoff[:alpha]`, `katz` or `interpolation[:w1,w2,...]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let args = parts.next();
+        let smoothing = match name {
+            "none" => Smoothing::None,
+            "katz" => Smoothing::Katz,
+            "backoff" => match args {
+                Some(alpha) => alpha
+                    .parse()
+                    .map(Smoothing::Backoff)
+                    .map_err(|err| format!("invalid alpha {:?}: {}", alpha, err))?,
+                None => Smoothing::Backoff(0.4),
+            },
+            "interpolation" => match args {
+                Some(weights) => weights
+                    .split(',')
+                    .map(|weight| {
+                        weight
+                            .parse()
+                            .map_err(|err| format!("invalid weight {:?}: {}", weight, err))
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(Smoothing::Interpolation)?,
+                None => Smoothing::Interpolation(vec![0.1, 0.3, 0.6]),
+            },
+            _ => return Err(format!("unknown smoothing {:?}", s)),
+        };
+        smoothing.validate().map_err(|err| err.to_string())?;
+        Ok(smoothing)
+    }
+}
+
+/// Models of order 1, 2, ... combined into a single language model
+pub struct Smoothed {
+    models: Vec<Box<dyn LanguageModel>>,
+    pub smoothing: Smoothing,
+    /// Mapping used instead of the one of the models, see `set_tones`
+    mapping: Option<BTreeMap<String, Vec<char>>>,
+    /// Confusions tolerated in the syllables to convert
+    pub fuzzy: Option<FuzzyPinyin>,
+}
+
+impl Smoothed {
+    /// `models[i]` must be of order `i + 1`, interpolation needs a weight
+    /// per model
+    pub fn new(models: Vec<Box<dyn LanguageModel>>, smoothing: Smoothing) -> Result<Self, Error> {
+        if models.is_empty() {
+            return Err(Error::InvalidModel("no models to smooth".to_string()));
+        }
+        for (i, model) in models.iter().enumerate() {
+            if model.order() != i + 1 {
+                return Err(Error::InvalidModel(format!(
+                    "model of order {} given as order {}",
+                    model.order(),
+                    i + 1
+                )));
+            }
+        }
+        smoothing.validate()?;
+        if let Smoothing::Interpolation(weights) = &smoothing {
+            if weights.len() != models.len() {
+                return Err(Error::InvalidSmoothing(format!(
+                    "{} interpolation weights for {} models",
+                    weights.len(),
+                    models.len()
+                )));
+            }
+        }
+        Ok(Smoothed {
+            models,
+            smoothing,
+            mapping: None,
+            fuzzy: None,
+        })
+    }
+
+    /// Spellings of syllables this model converts, those of the mapping and
+    /// their fuzzy alternatives
+    pub fn spellings(&self) -> BTreeSet<String> {
+        match &self.fuzzy {
+            Some(fuzzy) => fuzzy.spellings(self.mapping().keys()),
+            None => self.mapping().keys().cloned().collect(),
+        }
+    }
+
+    /// Tones stored in the metadata of the highest order model, whose
+    /// mapping is extended by `set_tones`, empty if it has none
+    pub fn stored_tones(&self) -> BTreeMap<String, Vec<char>> {
+        self.models[self.models.len() - 1]
+            .metadata()
+            .map(|metadata| metadata.tones.clone())
+            .unwrap_or_default()
+    }
+
+    /// Accept toned syllables like "zhong1" and "zhōng" besides the toneless
+    /// ones, restricted to the chars `tones` lists for them
+    pub fn set_tones(&mut self, tones: &BTreeMap<String, Vec<char>>) {
+        let mapping = self.models[self.models.len() - 1].mapping();
+        self.mapping = Some(tone_mapping(mapping, tones));
+    }
+
+    /// Convert a pinyin sentence to chinese, keeping the `n` best paths
+    /// for every decoder state
+    pub fn convert(&self, words: &[&str], n: usize) -> Result<Lattice, Error> {
+        self.convert_graph(&SyllableGraph::from_words(words), n)
+    }
+
+    /// Convert every segmentation in `graph` at once, the language model
+    /// picks the best one
+    pub fn convert_graph(&self, graph: &SyllableGraph, n: usize) -> Result<Lattice, Error> {
+        self.convert_graph_observed(graph, n, &mut ())
+    }
+
+    /// Like `convert_graph`, reporting the decoder internals to `observer`
+    pub fn convert_graph_observed(
+        &self,
+        graph: &SyllableGraph,
+        n: usize,
+        observer: &mut dyn Observer,
+    ) -> Result<Lattice, Error> {
+        let mut lattice = Lattice::default();
+        match &self.fuzzy {
+            Some(fuzzy) => {
+                let graph = fuzzy.expand_graph(graph, self.mapping());
+                lattice.extend_observed(self, &graph, n, observer)?
+            }
+            None => lattice.extend_observed(self, graph, n, observer)?,
+        }
+        Ok(lattice)
+    }
+
+    /// Convert a sentence with unknown tokens handled by `policy`, returning
+    /// the `n` best sentences with their log probabilities
+    pub fn convert_pieces(
+        &self,
+        pieces: &[Piece],
+        n: usize,
+        policy: UnknownPolicy,
+    ) -> Result<Vec<(String, f64)>, Error> {
+        self.convert_pieces_observed(pieces, n, policy, &mut ())
+    }
+
+    /// Like `convert_pieces`, reporting the decoder internals of every
+    /// decoded run of syllables to `observer`
+    pub fn convert_pieces_observed(
+        &self,
+        pieces: &[Piece],
+        n: usize,
+        policy: UnknownPolicy,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<(String, f64)>, Error> {
+        if policy == UnknownPolicy::Skip {
+            let mut graph = SyllableGraph::default();
+            for piece in pieces {
+                if let Piece::Syllables(syllables) = piece {
+                    graph.append(syllables);
+                }
+            }
+            return Ok(self.convert_graph_observed(&graph, n, observer)?.nbest(n));
+        }
+
+        let mut res = vec![(String::new(), 0.0)];
+        for piece in pieces {
+            let candidates = match piece {
+                Piece::Syllables(graph) => {
+                    self.convert_graph_observed(graph, n, observer)?.nbest(n)
+                }
+                Piece::Unknown { token, position } => match policy {
+                    UnknownPolicy::PassThrough => vec![(token.clone(), 0.0)],
+                    UnknownPolicy::Split => continue,
+                    _ => {
+                        return Err(Error::UnknownSyllable {
+                            token: token.clone(),
+                            position: *position,
+                        })
+                    }
+                },
+            };
+            let mut joined = Vec::with_capacity(res.len() * candidates.len());
+            for (prefix, prefix_score) in &res {
+                for (sentence, score) in &candidates {
+                    joined.push((format!("{}{}", prefix, sentence), prefix_score + score));
+                }
+            }
+            joined.sort_by(|a, b| b.1.total_cmp(&a.1));
+            joined.truncate(n);
+            res = joined;
+        }
+        Ok(res)
+    }
+
+    /// Log probability with the longest usable history, backing off to
+    /// shorter ones
+    fn backoff(&self, alpha: f64, history: &[char], ch: char) -> f64 {
+        let max_order = self.models.len().min(history.len() + 1);
+        let penalty = alpha.ln();
+        for (level, order) in (1..=max_order).rev().enumerate() {
+            let history = &history[history.len() + 1 - order..];
+            if let Some(log_prob) = self.models[order - 1].log_prob(history, ch) {
+                return level as f64 * penalty + log_prob;
+            }
+        }
+        (max_order - 1) as f64 * penalty + UNSEEN_PROB.ln()
+    }
+
+    /// Log probability with the longest usable history, backing off to
+    /// shorter ones with the stored weights
+    fn katz(&self, history: &[char], ch: char) -> f64 {
+        let max_order = self.models.len().min(history.len() + 1);
+        let mut weight = 0.0;
+        for order in (1..=max_order).rev() {
+            let history = &history[history.len() + 1 - order..];
+            if let Some(log_prob) = self.models[order - 1].log_prob(history, ch) {
+                return weight + log_prob;
+            }
+            if order > 1 {
+                weight += self.models[order - 2].backoff(history).unwrap_or(0.0);
+            }
+        }
+        weight + UNSEEN_PROB.ln()
+    }
+
+    fn interpolate(&self, weights: &[f64], history: &[char], ch: char) -> f64 {
+        let max_order = self.models.len().min(history.len() + 1);
+        let mut prob = 0.0;
+        let mut total = 0.0;
+        for order in 1..=max_order {
+            let history = &history[history.len() + 1 - order..];
+            total += weights[order - 1];
+            if let Some(log_prob) = self.models[order - 1].log_prob(history, ch) {
+                prob += weights[order - 1] * log_prob.exp();
+            }
+        }
+        if prob > 0.0 && total > 0.0 {
+            (prob / total).ln()
+        } else {
+            UNSEEN_PROB.ln()
+        }
+    }
+}
+
+impl LanguageModel for Smoothed {
+    fn order(&self) -> usize {
+        self.models.len()
+    }
+
+    fn mapping(&self) -> &BTreeMap<String, Vec<char>> {
+        match &self.mapping {
+            Some(mapping) => mapping,
+            None => self.models[self.models.len() - 1].mapping(),
+        }
+    }
+
+    fn log_prob(&self, history: &[char], ch: char) -> Option<f64> {
+        match &self.smoothing {
+            Smoothing::None => {
+                let order = self.models.len().min(history.len() + 1);
+                self.models[order - 1].log_prob(history, ch)
+            }
+            Smoothing::Backoff(alpha) => Some(self.backoff(*alpha, history, ch)),
+            Smoothing::Katz => Some(self.katz(history, ch)),
+            Smoothing::Interpolation(weights) => Some(self.interpolate(weights, history, ch)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn models() -> Vec<Box<dyn LanguageModel>> {
+        vec![
+            Box::new(Model::<Match1>::empty()),
+            Box::new(Model::<Match2>::empty()),
+            Box::new(Model::<Match3>::empty()),
+        ]
+    }
+
+    #[test]
+    fn parse_rejects_invalid_parameters() {
+        assert_eq!("backoff".parse(), Ok(Smoothing::Backoff(0.4)));
+        assert_eq!("backoff:1".parse(), Ok(Smoothing::Backoff(1.0)));
+        for s in &[
+            "backoff:0",
+            "backoff:-1",
+            "backoff:1.5",
+            "backoff:NaN",
+            "interpolation:0,0,0",
+            "interpolation:-1,1,1",
+            "interpolation:NaN,1,1",
+        ] {
+            assert!(s.parse::<Smoothing>().is_err(), "{} accepted", s);
+        }
+    }
+
+    #[test]
+    fn new_checks_weight_count() {
+        let smoothing = Smoothing::Interpolation(vec![0.5, 0.5]);
+        assert!(matches!(
+            Smoothed::new(models(), smoothing),
+            Err(Error::InvalidSmoothing(_))
+        ));
+        let smoothing = Smoothing::Interpolation(vec![0.2, 0.3, 0.5]);
+        assert!(Smoothed::new(models(), smoothing).is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+use super::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Bytes of an entry in a run file: a big-endian `u64` key and `u32` count
+const ENTRY_LEN: usize = 12;
+
+/// Most run files open at once, more are merged in several passes
+const MAX_FAN_IN: usize = 64;
+
+/// Write sorted `entries` to a new run file at `path`
+fn write_run<I>(path: &Path, entries: I) -> Result<(), Error>
+where
+    I: IntoIterator<Item = Result<(u64, u32), Error>>,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+    for entry in entries {
+        let (key, count) = entry?;
+        writer.write_all(&key.to_be_bytes())?;
+        writer.write_all(&count.to_be_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Counts n-grams like `Counts`, but writes the tables to sorted run files
+/// whenever they take more than `limit` bytes
+pub struct SpillingCounts {
+    counts: Counts,
+    dir: PathBuf,
+    name: String,
+    limit: usize,
+    /// run files of every order
+    runs: Vec<Vec<PathBuf>>,
+}
+
+impl SpillingCounts {
+    /// Run files are created in `dir` with names starting with `name`,
+    /// which must be unique among the counters sharing `dir`
+    pub fn new<P: Into<PathBuf>>(
+        order: usize,
+        dir: P,
+        name: &str,
+        limit: usize,
+    ) -> Result<Self, Error> {
+        Ok(SpillingCounts {
+            counts: Counts::new(order)?,
+            dir: dir.into(),
+            name: name.to_string(),
+            limit,
+            runs: vec![Vec::new(); order],
+        })
+    }
+
+    pub fn add_text(&mut self, text: &str, vocab: &Vocab) -> Result<(), Error> {
+        self.counts.add_text(text, vocab);
+        if self.counts.memory() > self.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Add the counts of `counts`, which must use the same vocabulary
+    pub fn merge(&mut self, counts: Counts) -> Result<(), Error> {
+        self.counts.merge(counts);
+        if self.counts.memory() > self.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Write the counts held in memory to a new run file per order
+    pub fn spill(&mut self) -> Result<(), Error> {
+        for order in 1..=self.counts.order() {
+            let mut entries: Vec<(u64, u32)> = self.counts.take_table(order).into_iter().collect();
+            if entries.is_empty() {
+                continue;
+            }
+            entries.sort_unstable();
+            let runs = &mut self.runs[order - 1];
+            let path = self
+                .dir
+                .join(format!("{}-order{}-{}.run", self.name, order, runs.len()));
+            write_run(&path, entries.into_iter().map(Ok))?;
+            runs.push(path);
+        }
+        Ok(())
+    }
+
+    /// Spill what is left and return the run files of every order
+    pub fn finish(mut self) -> Result<Vec<Vec<PathBuf>>, Error> {
+        self.spill()?;
+        Ok(self.runs)
+    }
+}
+
+/// Sorted `(key, count)` entries of a run file
+pub struct Run {
+    reader: BufReader<File>,
+}
+
+impl Run {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Run {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+}
+
+impl Iterator for Run {
+    type Item = Result<(u64, u32), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut entry = [0; ENTRY_LEN];
+        match self.reader.read_exact(&mut entry) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+        let key = u64::from_be_bytes(entry[..8].try_into().unwrap());
+        let count = u32::from_be_bytes(entry[8..].try_into().unwrap());
+        Some(Ok((key, count)))
+    }
+}
+
+/// The entries of several runs merged into one sorted sequence, counts of
+/// the same key added up
+pub struct MergedRuns {
+    runs: Vec<Run>,
+    /// next entry of every run that is not exhausted, with the run's index
+    heap: BinaryHeap<Reverse<(u64, u32, usize)>>,
+}
+
+impl MergedRuns {
+    /// Merge the runs at `paths`, at most `MAX_FAN_IN` at a time: more runs
+    /// are first merged into intermediate run files next to them
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Self, Error> {
+        let mut paths: Vec<PathBuf> = paths.iter().map(|path| path.as_ref().into()).collect();
+        let mut intermediate = false;
+        while paths.len() > MAX_FAN_IN {
+            let mut merged = Vec::new();
+            for group in paths.chunks(MAX_FAN_IN) {
+                let path = group[0].with_extension("merged.run");
+                write_run(&path, Self::open_all(group)?)?;
+                if intermediate {
+                    for path in group {
+                        fs::remove_file(path)?;
+                    }
+                }
+                merged.push(path);
+            }
+            paths = merged;
+            intermediate = true;
+        }
+        Self::open_all(&paths)
+    }
+
+    fn open_all(paths: &[PathBuf]) -> Result<Self, Error> {
+        let mut merged = MergedRuns {
+            runs: Vec::with_capacity(paths.len()),
+            heap: BinaryHeap::new(),
+        };
+        for path in paths {
+            merged.runs.push(Run::open(path)?);
+            merged.advance(merged.runs.len() - 1)?;
+        }
+        Ok(merged)
+    }
+
+    fn advance(&mut self, index: usize) -> Result<(), Error> {
+        if let Some(entry) = self.runs[index].next() {
+            let (key, count) = entry?;
+            self.heap.push(Reverse((key, count, index)));
+        }
+        Ok(())
+    }
+
+    fn merge_next(&mut self) -> Result<Option<(u64, u32)>, Error> {
+        let Reverse((key, mut count, index)) = match self.heap.pop() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.advance(index)?;
+        while let Some(Reverse((next, _, _))) = self.heap.peek() {
+            if *next != key {
+                break;
+            }
+            let Reverse((_, other, index)) = self.heap.pop().unwrap();
+            count += other;
+            self.advance(index)?;
+        }
+        Ok(Some((key, count)))
+    }
+}
+
+impl Iterator for MergedRuns {
+    type Item = Result<(u64, u32), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.merge_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn spilled_runs_merge_in_passes() {
+        let dir = std::env::temp_dir().join(format!("pinyin-spill-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vocab = Vocab::from_mapping(&mapping_of("xyz")).unwrap();
+        let texts = ["xyz", "yzx", "zzy"];
+        let mut counts = Counts::new(2).unwrap();
+        // a limit of 0 spills every text to its own runs
+        let mut spilling = SpillingCounts::new(2, &dir, "test", 0).unwrap();
+        for i in 0..MAX_FAN_IN * 2 + 1 {
+            counts.add_text(texts[i % texts.len()], &vocab);
+            spilling.add_text(texts[i % texts.len()], &vocab).unwrap();
+        }
+        let runs = spilling.finish().unwrap();
+        for order in 1..=2 {
+            assert_eq!(runs[order - 1].len(), MAX_FAN_IN * 2 + 1);
+            let merged: Vec<(u64, u32)> = MergedRuns::open(&runs[order - 1])
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(merged, counts.sorted(order));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
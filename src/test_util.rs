@@ -0,0 +1,18 @@
+use super::*;
+
+/// Mapping of the syllable "a" to `chars`
+pub fn mapping_of(chars: &str) -> BTreeMap<String, Vec<char>> {
+    vec![("a".to_string(), chars.chars().collect())]
+        .into_iter()
+        .collect()
+}
+
+/// Model of the chars x, y and z with the probabilities of `probs`,
+/// n-grams like "xy"
+pub fn model<T: Match>(probs: &[(&str, f64)]) -> Model<T> {
+    let mut model = Model::from_mapping(mapping_of("xyz")).unwrap();
+    for (ngram, prob) in probs {
+        model.insert_prob(&T::from_str(ngram), *prob).unwrap();
+    }
+    model
+}
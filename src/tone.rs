@@ -0,0 +1,194 @@
+use super::*;
+use std::collections::BTreeSet;
+
+/// Vowels, ü written as v, with their forms marked with tones 1 to 4
+const MARKED_VOWELS: [(char, [char; 4]); 6] = [
+    ('a', ['ā', 'á', 'ǎ', 'à']),
+    ('e', ['ē', 'é', 'ě', 'è']),
+    ('i', ['ī', 'í', 'ǐ', 'ì']),
+    ('o', ['ō', 'ó', 'ǒ', 'ò']),
+    ('u', ['ū', 'ú', 'ǔ', 'ù']),
+    ('v', ['ǖ', 'ǘ', 'ǚ', 'ǜ']),
+];
+
+/// Combining marks of tones 1 to 4
+const COMBINING_MARKS: [char; 4] = ['\u{304}', '\u{301}', '\u{30c}', '\u{300}'];
+
+/// Precomposed nasals of syllables without a vowel and their tones
+const MARKED_NASALS: [(char, char, u8); 4] =
+    [('ḿ', 'm', 2), ('ń', 'n', 2), ('ň', 'n', 3), ('ǹ', 'n', 4)];
+
+/// Toneless vowel, ü written as v, and tone of a vowel with a tone mark
+fn unmark(ch: char) -> Option<(char, u8)> {
+    for (vowel, marked) in &MARKED_VOWELS {
+        if let Some(index) = marked.iter().position(|marked| *marked == ch) {
+            return Some((*vowel, index as u8 + 1));
+        }
+    }
+    MARKED_NASALS
+        .iter()
+        .find(|(marked, _, _)| *marked == ch)
+        .map(|(_, nasal, tone)| (*nasal, *tone))
+}
+
+/// `ch` with the tone mark of `tone` from 1 to 4, as a string since some
+/// have no precomposed form
+fn mark(ch: char, tone: u8) -> String {
+    if let Some((_, marked)) = MARKED_VOWELS.iter().find(|(vowel, _)| *vowel == ch) {
+        return marked[tone as usize - 1].to_string();
+    }
+    if let Some((marked, _, _)) = MARKED_NASALS
+        .iter()
+        .find(|(_, nasal, marked_tone)| *nasal == ch && *marked_tone == tone)
+    {
+        return marked.to_string();
+    }
+    format!("{}{}", ch, COMBINING_MARKS[tone as usize - 1])
+}
+
+/// Replace vowels followed by a combining tone mark with their precomposed
+/// form, other chars are kept
+pub fn compose_tone_marks(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let tone = chars
+            .peek()
+            .and_then(|next| COMBINING_MARKS.iter().position(|mark| mark == next));
+        let base = if ch == 'ü' { 'v' } else { ch };
+        match tone.map(|tone| mark(base, tone as u8 + 1)) {
+            Some(marked) if marked.chars().count() == 1 => {
+                res.push_str(&marked);
+                chars.next();
+            }
+            _ => res.push(ch),
+        }
+    }
+    res
+}
+
+/// Split a pinyin syllable into its lowercase toneless spelling, ü and "u:"
+/// written as v, and its tone from a tone number or a tone mark anywhere in
+/// it, 5 for the neutral tone written as 5 or 0
+pub fn parse_syllable(syllable: &str) -> (String, Option<u8>) {
+    let mut base = String::with_capacity(syllable.len());
+    let mut tone = None;
+    for ch in syllable.trim().to_lowercase().chars() {
+        if let Some((toneless, marked)) = unmark(ch) {
+            base.push(toneless);
+            tone = Some(marked);
+            continue;
+        }
+        if let Some(index) = COMBINING_MARKS.iter().position(|mark| *mark == ch) {
+            tone = Some(index as u8 + 1);
+            continue;
+        }
+        match ch {
+            'ü' => base.push('v'),
+            'ê' => base.push('e'),
+            '1'..='4' => tone = Some(ch as u8 - b'0'),
+            '5' | '0' => tone = Some(5),
+            ch => base.push(ch),
+        }
+    }
+    (base.replace("u:", "v"), tone)
+}
+
+/// Spelling of a toneless syllable with a tone number, e.g. "lv4"
+pub fn numbered(base: &str, tone: u8) -> String {
+    format!("{}{}", base, tone)
+}
+
+/// Every spelling of a toneless syllable with the mark of `tone` from 1 to 4
+/// on one of its vowels, the standard placement and the misplaced ones,
+/// with ü for v
+fn marked_spellings(base: &str, tone: u8) -> Vec<String> {
+    let chars: Vec<char> = base.chars().collect();
+    let vowels: Vec<usize> = (0..chars.len())
+        .filter(|i| "aeiouv".contains(chars[*i]))
+        .collect();
+    // syllables like "m" and "ng" carry the mark on their first letter
+    let positions = if vowels.is_empty() { vec![0] } else { vowels };
+    positions
+        .into_iter()
+        .map(|position| {
+            chars
+                .iter()
+                .enumerate()
+                .map(|(i, ch)| match (i == position, ch) {
+                    (true, ch) => mark(*ch, tone),
+                    (false, 'v') => "ü".to_string(),
+                    (false, ch) => ch.to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// `mapping` extended with the toned spellings of its syllables: tone
+/// numbers like "zhong1" and "ma0" or "ma5" for the neutral tone, tone marks
+/// like "zhōng" and ü for v
+///
+/// A toned syllable yields the chars `tones` lists under its numbered
+/// spelling. If none of them is in `mapping` it falls back to all chars of
+/// the toneless syllable, like the toneless syllable itself does.
+pub fn tone_mapping(
+    mapping: &BTreeMap<String, Vec<char>>,
+    tones: &BTreeMap<String, Vec<char>>,
+) -> BTreeMap<String, Vec<char>> {
+    let mut res = mapping.clone();
+    for (base, chars) in mapping {
+        let umlaut = base.replace('v', "ü");
+        if umlaut != *base {
+            res.insert(umlaut.clone(), chars.clone());
+        }
+        for tone in 1..=5 {
+            let toned: BTreeSet<char> = tones
+                .get(&numbered(base, tone))
+                .map_or_else(BTreeSet::new, |toned| toned.iter().copied().collect());
+            let mut candidates: Vec<char> = chars
+                .iter()
+                .filter(|ch| toned.contains(ch))
+                .copied()
+                .collect();
+            if candidates.is_empty() {
+                candidates = chars.clone();
+            }
+            let mut spellings = vec![numbered(base, tone)];
+            if umlaut != *base {
+                spellings.push(numbered(&umlaut, tone));
+            }
+            if tone < 5 {
+                spellings.extend(marked_spellings(base, tone));
+            } else {
+                spellings.push(numbered(base, 0));
+                if umlaut != *base {
+                    spellings.push(numbered(&umlaut, 0));
+                }
+            }
+            for spelling in spellings {
+                res.insert(spelling, candidates.clone());
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neutral_tone_spellings() {
+        assert_eq!(parse_syllable("ma0"), ("ma".to_string(), Some(5)));
+        assert_eq!(parse_syllable("ma5"), ("ma".to_string(), Some(5)));
+        let mapping = vec![("ma".to_string(), vec!['吗', '妈'])]
+            .into_iter()
+            .collect();
+        let tones = vec![("ma5".to_string(), vec!['吗'])].into_iter().collect();
+        let toned = tone_mapping(&mapping, &tones);
+        assert_eq!(toned["ma0"], vec!['吗']);
+        assert_eq!(toned["ma5"], vec!['吗']);
+        assert_eq!(toned["ma1"], vec!['吗', '妈']);
+    }
+}
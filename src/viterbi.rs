@@ -0,0 +1,260 @@
+use super::*;
+
+/// A node in the Viterbi lattice
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    /// chinese character chosen for this syllable
+    pub ch: char,
+    /// log probability of the best path ending at this node
+    pub score: f64,
+    /// column and index of the previous node
+    pub back: Option<(usize, usize)>,
+}
+
+/// Viterbi lattice over a `SyllableGraph`, `columns[i]` holds the nodes of
+/// syllables ending at position `i + 1`
+#[derive(Debug, Clone, Default)]
+pub struct Lattice {
+    pub columns: Vec<Vec<Node>>,
+}
+
+impl Lattice {
+    /// Collect at most `len` nodes of the path ending at `columns[column][index]`
+    pub fn nodes(&self, column: usize, index: usize, len: usize) -> Vec<Node> {
+        let mut res = Vec::with_capacity(len);
+        let mut cur = Some((column, index));
+        while res.len() < len {
+            match cur {
+                Some((column, index)) => {
+                    let node = self.columns[column][index];
+                    res.push(node);
+                    cur = node.back;
+                }
+                None => break,
+            }
+        }
+        res.reverse();
+        res
+    }
+
+    /// Collect at most `len` characters of the path ending at `columns[column][index]`
+    pub fn history(&self, column: usize, index: usize, len: usize) -> Vec<char> {
+        self.nodes(column, index, len)
+            .into_iter()
+            .map(|node| node.ch)
+            .collect()
+    }
+
+    /// Nodes of the most likely path
+    pub fn best_path(&self) -> Vec<Node> {
+        let last = match self.columns.last() {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        // the first of equally likely nodes, like `nbest`
+        let best = (0..last.len())
+            .rev()
+            .max_by(|a, b| last[*a].score.total_cmp(&last[*b].score));
+        match best {
+            Some(index) => self.nodes(self.columns.len() - 1, index, self.columns.len()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Follow the backpointers from the node `index` in the last column
+    pub fn path(&self, index: usize) -> String {
+        match self.columns.len() {
+            0 => String::new(),
+            len => self.history(len - 1, index, len).into_iter().collect(),
+        }
+    }
+
+    /// Most likely sentence and its log probability
+    pub fn best(&self) -> Option<(String, f64)> {
+        self.nbest(1).pop()
+    }
+
+    /// At most `n` most likely distinct sentences with their log
+    /// probabilities, best first
+    pub fn nbest(&self, n: usize) -> Vec<(String, f64)> {
+        let last = match self.columns.last() {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        let mut order: Vec<usize> = (0..last.len()).collect();
+        order.sort_by(|a, b| last[*b].score.total_cmp(&last[*a].score));
+        let mut res: Vec<(String, f64)> = Vec::with_capacity(n);
+        for index in order {
+            if res.len() == n {
+                break;
+            }
+            let path = self.path(index);
+            if res.iter().all(|(sentence, _)| *sentence != path) {
+                res.push((path, last[index].score));
+            }
+        }
+        res
+    }
+
+    /// Decode the syllables of `graph` ending after `self.columns.len()`
+    /// with `model`, keeping the `n` best paths for every decoder state
+    ///
+    /// With `n` of 0 no path is kept and the new columns stay empty.
+    pub fn extend<L: LanguageModel + ?Sized>(
+        &mut self,
+        model: &L,
+        graph: &SyllableGraph,
+        n: usize,
+    ) -> Result<(), Error> {
+        self.extend_observed(model, graph, n, &mut ())
+    }
+
+    /// Like `extend`, reporting the graph, every step and the best path
+    /// to `observer`
+    pub fn extend_observed<L: LanguageModel + ?Sized>(
+        &mut self,
+        model: &L,
+        graph: &SyllableGraph,
+        n: usize,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Error> {
+        let history_len = model.order() - 1;
+        observer.graph(graph);
+        for end in self.columns.len() + 1..=graph.len {
+            let mut states: HashMap<Vec<char>, Vec<usize>> = HashMap::new();
+            let mut column: Vec<Node> = Vec::new();
+            for edge in graph.ending_at(end) {
+                let chars =
+                    model
+                        .candidates(&edge.syllable)
+                        .ok_or_else(|| Error::UnknownSyllable {
+                            token: edge.syllable.clone(),
+                            position: edge.start,
+                        })?;
+                let prev: Vec<(Option<(usize, usize)>, f64)> = if edge.start == 0 {
+                    vec![(None, 0.0)]
+                } else {
+                    self.columns[edge.start - 1]
+                        .iter()
+                        .enumerate()
+                        .map(|(index, node)| (Some((edge.start - 1, index)), node.score))
+                        .collect()
+                };
+
+                for (back, prev_score) in prev {
+                    let history = match back {
+                        Some((column, index)) => self.history(column, index, history_len),
+                        None => Vec::new(),
+                    };
+                    for ch in chars {
+                        if let Some(log_prob) = model.log_prob(&history, *ch) {
+                            let node = Node {
+                                ch: *ch,
+                                score: prev_score + log_prob + edge.log_weight,
+                                back,
+                            };
+                            let mut state = history.clone();
+                            state.push(*ch);
+                            if state.len() > history_len {
+                                state.remove(0);
+                            }
+                            let kept = states.entry(state).or_default();
+                            if kept.len() < n {
+                                kept.push(column.len());
+                                column.push(node);
+                            } else if let Some(worst) = kept
+                                .iter()
+                                .copied()
+                                .min_by(|a, b| column[*a].score.total_cmp(&column[*b].score))
+                            {
+                                if node.score > column[worst].score {
+                                    column[worst] = node;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            observer.step(end, &column);
+            self.columns.push(column);
+        }
+        observer.path(&self.best_path());
+        Ok(())
+    }
+}
+
+impl<T: Match> LanguageModel for Model<T> {
+    fn order(&self) -> usize {
+        T::order()
+    }
+
+    fn mapping(&self) -> &BTreeMap<String, Vec<char>> {
+        &self.mapping
+    }
+
+    fn log_prob(&self, history: &[char], ch: char) -> Option<f64> {
+        if history.len() + 1 < T::order() {
+            return None;
+        }
+        let history = &history[history.len() + 1 - T::order()..];
+        let key = self.vocab.key(history.iter().copied().chain(Some(ch)))?;
+        self.prob.get(&key).map(|prob| prob.ln())
+    }
+
+    fn backoff(&self, context: &[char]) -> Option<f64> {
+        if context.len() != T::order() {
+            return None;
+        }
+        let key = self.vocab.key(context.iter().copied())?;
+        self.backoff.get(&key).map(|weight| weight.ln())
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        Some(&self.metadata)
+    }
+}
+
+impl<T: Match> Model<T> {
+    /// Convert a pinyin sentence to chinese
+    ///
+    /// `last` is the lattice decoded by the lower order model, it must already
+    /// cover the first `T::order() - 1` syllables of `words`.
+    pub fn convert(&self, words: &[&str], last: Option<Lattice>) -> Result<Lattice, Error> {
+        self.convert_nbest(words, last, 1)
+    }
+
+    /// Like `convert`, but keep the `n` best paths for every decoder state,
+    /// so that `Lattice::nbest(n)` returns the exact top `n` sentences
+    pub fn convert_nbest(
+        &self,
+        words: &[&str],
+        last: Option<Lattice>,
+        n: usize,
+    ) -> Result<Lattice, Error> {
+        let mut lattice = last.unwrap_or_default();
+        if lattice.columns.len() + 1 < T::order() {
+            return Err(Error::InvalidModel(format!(
+                "model of order {} needs the lattice of {} syllables decoded by the lower orders",
+                T::order(),
+                T::order() - 1
+            )));
+        }
+        lattice.extend(self, &SyllableGraph::from_words(words), n)?;
+        Ok(lattice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn higher_orders_need_a_lattice() {
+        let model = model::<Match3>(&[]);
+        assert!(matches!(
+            model.convert(&["a"], None),
+            Err(Error::InvalidModel(_))
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use super::*;
+
+/// Bits of a char id inside a packed n-gram key, like the ids of the
+/// binary format
+pub const ID_BITS: u32 = 16;
+
+/// Longest n-gram that fits into a packed key
+pub const MAX_ORDER: usize = (u64::BITS / ID_BITS) as usize;
+
+/// Most chars a vocabulary can hold
+pub const MAX_CHARS: usize = 1 << ID_BITS;
+
+/// Fails if n-grams of `order` do not fit into a packed key
+pub fn check_order(order: usize) -> Result<(), Error> {
+    if order == 0 || order > MAX_ORDER {
+        return Err(Error::TooLarge(format!(
+            "n-grams of order {} do not fit into a packed key, at most {} chars",
+            order, MAX_ORDER
+        )));
+    }
+    Ok(())
+}
+
+/// Dense integer ids of chinese characters, in the order they were interned
+///
+/// N-grams are packed into a `u64` key, `ID_BITS` per char with the last
+/// char in the lowest bits, so that the key of the prefix of an n-gram is
+/// `key >> ID_BITS`. Keys of different orders may collide, keep them apart.
+#[derive(Debug, Clone, Default)]
+pub struct Vocab {
+    chars: Vec<char>,
+    ids: HashMap<char, u32>,
+}
+
+impl Vocab {
+    pub fn new() -> Self {
+        Vocab::default()
+    }
+
+    /// Intern every char of `mapping`, in the order of the mapping
+    pub fn from_mapping(mapping: &BTreeMap<String, Vec<char>>) -> Result<Self, Error> {
+        let mut vocab = Vocab::new();
+        for chars in mapping.values() {
+            for ch in chars {
+                vocab.intern(*ch)?;
+            }
+        }
+        Ok(vocab)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// All interned chars, indexed by id
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    /// Id of `ch`, assigning the next one if it is new, fails once
+    /// `MAX_CHARS` are taken
+    pub fn intern(&mut self, ch: char) -> Result<u32, Error> {
+        if let Some(id) = self.ids.get(&ch) {
+            return Ok(*id);
+        }
+        if self.chars.len() >= MAX_CHARS {
+            return Err(Error::TooLarge(format!(
+                "more than {} distinct chars",
+                MAX_CHARS
+            )));
+        }
+        let id = self.chars.len() as u32;
+        self.chars.push(ch);
+        self.ids.insert(ch, id);
+        Ok(id)
+    }
+
+    pub fn id(&self, ch: char) -> Option<u32> {
+        self.ids.get(&ch).copied()
+    }
+
+    /// The char of `id`, which must have been interned
+    pub fn char(&self, id: u32) -> char {
+        self.chars[id as usize]
+    }
+
+    /// Packed key of an n-gram, `None` if a char is not interned or it is
+    /// longer than `MAX_ORDER`, so that it cannot have been stored
+    pub fn key<I: IntoIterator<Item = char>>(&self, chars: I) -> Option<u64> {
+        let mut key = 0;
+        for (i, ch) in chars.into_iter().enumerate() {
+            if i >= MAX_ORDER {
+                return None;
+            }
+            key = key << ID_BITS | self.id(ch)? as u64;
+        }
+        Some(key)
+    }
+
+    /// Packed key of an n-gram, interning its chars
+    pub fn intern_key(&mut self, chars: &[char]) -> Result<u64, Error> {
+        check_order(chars.len())?;
+        let mut key = 0;
+        for ch in chars {
+            key = key << ID_BITS | self.intern(*ch)? as u64;
+        }
+        Ok(key)
+    }
+
+    /// The `order` chars packed into `key`
+    pub fn unpack(&self, key: u64, order: usize) -> Vec<char> {
+        (0..order)
+            .rev()
+            .map(|i| self.char((key >> (i as u32 * ID_BITS)) as u32 & ((1 << ID_BITS) - 1)))
+            .collect()
+    }
+
+    /// Key of all chars but the last one of the n-gram packed into `key`
+    pub fn prefix(key: u64) -> u64 {
+        key >> ID_BITS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn four_grams_fit() {
+        let mut model = Model::<NGram<4>>::empty();
+        let ngram = NGram::<4>::from_str("我爱北京");
+        model.insert_prob(&ngram, 0.5).unwrap();
+        assert_eq!(model.get_prob(&ngram), Some(0.5));
+        assert_eq!(model.probs().collect::<Vec<_>>(), vec![(ngram, 0.5)]);
+        assert!(Counts::new(4).is_ok());
+    }
+
+    #[test]
+    fn longer_ngrams_are_an_error() {
+        let mut model = Model::<NGram<5>>::empty();
+        let ngram = NGram::<5>::from_str("我爱北京天");
+        assert!(matches!(
+            model.insert_prob(&ngram, 0.5),
+            Err(Error::TooLarge(_))
+        ));
+        assert_eq!(model.get_prob(&ngram), None);
+        assert!(matches!(Counts::new(5), Err(Error::TooLarge(_))));
+    }
+
+    #[test]
+    fn too_many_chars_are_an_error() {
+        let mut vocab = Vocab::new();
+        for id in 0..MAX_CHARS as u32 {
+            assert_eq!(
+                vocab
+                    .intern(std::char::from_u32(0x10000 + id).unwrap())
+                    .unwrap(),
+                id
+            );
+        }
+        assert!(vocab.intern('a').is_err());
+        assert_eq!(vocab.intern('\u{10000}').unwrap(), 0);
+    }
+}